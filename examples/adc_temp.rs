@@ -6,7 +6,7 @@ use core::fmt::Write;
 use core::writeln;
 
 use embedded_hal_1::delay::DelayUs;
-use hal::adc::{adc_to_temperature_celsius, Adc};
+use hal::adc::Adc;
 use hal::dma::NoDma;
 use hal::gpio::{AnyPin, Input, Level, Output, OutputDrive, Pull};
 use hal::interrupt::Interrupt;
@@ -58,23 +58,23 @@ fn main() -> ! {
     writeln!(serial, "mias: 0x{:08x?}", mias.bits());
 
     // ADC part
-    let mut adc_config = hal::adc::Config::for_temperature();
-    let mut adc = Adc::new(p.ADC, adc_config);
-
-    let mut temp_sensor = adc.enable_temperature();
+    let mut adc = Adc::new(p.ADC, hal::adc::Config::for_vbat());
+    let mut vbat_channel = adc.enable_vbat();
 
     loop {
         blue_led.toggle();
 
         let now = rtc.now();
 
-        let raw_temp = adc.read(&mut temp_sensor);
+        let mut temp_sensor = adc.temperature_sensor();
+        let raw_temp = temp_sensor.read_raw();
         writeln!(serial, "ADC raw data: {}", raw_temp).unwrap();
-        let temp = adc_to_temperature_celsius(raw_temp);
-        writeln!(serial, "sensor temp: {}C", temp).unwrap();
+        let temp = temp_sensor.read_milli_celsius();
+        writeln!(serial, "sensor temp: {}mC", temp).unwrap();
+        drop(temp_sensor);
 
-        let vi = adc.read_as_millivolts(&mut temp_sensor);
-        writeln!(serial, "ADC voltage: {}mV", vi).unwrap();
+        let vi = adc.read_as_millivolts(&mut vbat_channel);
+        writeln!(serial, "Vbat voltage: {}mV", vi).unwrap();
 
         /*
 
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use core::arch::global_asm;
+
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Timer};
+use hal::embassy::executor::{Executor, InterruptExecutor};
+use hal::gpio::{AnyPin, Level, Output, OutputDrive, Pin};
+use hal::interrupt::Priority;
+use {ch58x_hal as hal, panic_halt as _};
+
+static mut EXECUTOR: Option<Executor> = None;
+static EXECUTOR_HIGH: InterruptExecutor = InterruptExecutor::new();
+
+global_asm!(
+    r#"
+    .section .trap, "ax"
+    .global Software
+    Software:
+    addi sp, sp, -4
+    sw ra, 0(sp)
+    jal _rust_Software
+    lw ra, 0(sp)
+    addi sp, sp, 4
+    mret
+"#
+);
+
+#[allow(non_snake_case)]
+#[export_name = "_rust_Software"]
+unsafe fn Software_IRQHandler() {
+    EXECUTOR_HIGH.on_interrupt();
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn blink(pin: AnyPin, interval: Duration) {
+    let mut led = Output::new(pin, Level::Low, OutputDrive::Low);
+
+    loop {
+        led.toggle();
+        Timer::after(interval).await;
+    }
+}
+
+#[ch32v_rt::entry]
+fn main() -> ! {
+    let mut config = hal::Config::default();
+    config.clock.use_pll_60mhz();
+
+    let p = hal::init(config);
+
+    // runs at higher priority, preempts the thread mode tasks
+    let spawner = EXECUTOR_HIGH.start(hal::rt::Interrupt::Software, Priority::P1);
+    spawner
+        .spawn(blink(p.PB4.degrade(), Duration::from_millis(100)))
+        .unwrap();
+
+    let executor = unsafe {
+        EXECUTOR = Some(Executor::new());
+        EXECUTOR.as_mut().unwrap()
+    };
+    executor.run(|spawner: Spawner| {
+        spawner
+            .spawn(blink(p.PA8.degrade(), Duration::from_millis(500)))
+            .unwrap();
+    })
+}
@@ -129,7 +129,8 @@ extern "C" fn main() -> ! {
         hour: 18,
         minute: 45,
         second: 0,
-    });
+    })
+    .unwrap();
     */
 
     // let buf = hal::isp::read_eeprom(0x0, 500);
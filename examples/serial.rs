@@ -94,7 +94,8 @@ fn main() -> ! {
         hour: 15,
         minute: 42,
         second: 10,
-    });
+    })
+    .unwrap();
 
     let _ = serial.blocking_flush();
     writeln!(serial, "\n\nHello WCH! 🦀").unwrap();
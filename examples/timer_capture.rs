@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::writeln;
+
+use embedded_hal_1::delay::DelayUs;
+use hal::gpio::{Level, Output, OutputDrive};
+use hal::systick::SysTick;
+use hal::timer::{FrequencyMeter, PulseWidth};
+use hal::uart::UartTx;
+use {ch58x_hal as hal, panic_halt as _};
+
+#[ch32v_rt::entry]
+fn main() -> ! {
+    let mut config = hal::Config::default();
+    config.clock.use_pll_60mhz();
+
+    let p = hal::init(config);
+
+    let mut delay = SysTick::new(p.SYSTICK);
+
+    // LED PA8
+    let mut blue_led = Output::new(p.PA8, Level::Low, OutputDrive::Low);
+
+    let mut serial = UartTx::new(p.UART1, p.PA9, Default::default()).unwrap();
+
+    writeln!(serial, "\n\n\nHello World!").unwrap();
+    writeln!(serial, "Clocks: {}", hal::sysctl::clocks().hclk).unwrap();
+
+    // tachometer on TMR1, PA10
+    let mut tacho = FrequencyMeter::new(p.TMR1, p.PA10);
+    // IR receiver on TMR2, PA11
+    let mut ir = PulseWidth::new(p.TMR2, p.PA11);
+    // 10ms without edge ends a frame
+    ir.set_timeout(hal::sysctl::clocks().hclk.to_Hz() / 100);
+
+    loop {
+        blue_led.toggle();
+
+        match tacho.blocking_frequency_millihertz(4) {
+            Ok(mhz) => writeln!(serial, "tacho: {}.{:03}Hz", mhz / 1000, mhz % 1000).unwrap(),
+            Err(e) => writeln!(serial, "tacho: {:?}", e).unwrap(),
+        }
+
+        match ir.blocking_measure() {
+            Ok(m) => writeln!(
+                serial,
+                "pulse: high={} low={} duty={}‰ freq={}",
+                m.high_ticks,
+                m.low_ticks,
+                m.duty_permille(),
+                m.frequency()
+            )
+            .unwrap(),
+            Err(e) => writeln!(serial, "pulse: {:?}", e).unwrap(),
+        }
+
+        delay.delay_ms(1000);
+    }
+}
@@ -1,9 +1,45 @@
+use core::cell::Cell;
+use core::future::poll_fn;
 use core::ptr;
+use core::task::Poll;
 
-use crate::{into_ref, pac, peripherals, Peripheral};
+use critical_section::Mutex;
+use embassy_sync::waitqueue::AtomicWaker;
+use fugit::HertzU32 as Hertz;
+
+use crate::interrupt::Interrupt;
+use crate::{into_ref, pac, peripherals, Peripheral, PeripheralRef};
 
 const ROM_CFG_TMP_25C: *const u32 = 0x7F014 as *const u32;
 
+// R8_ADC_CTRL_DMA
+const RB_ADC_DMA_ENABLE: u8 = 0x01;
+const RB_ADC_DMA_LOOP: u8 = 0x04;
+const RB_ADC_IE_EOC: u8 = 0x10;
+const RB_ADC_CONT_EN: u8 = 0x40;
+const RB_ADC_AUTO_EN: u8 = 0x80;
+// R8_ADC_DMA_IF
+const RB_ADC_IF_DMA_END: u8 = 0x08;
+// R8_ADC_INT_FLAG
+const RB_ADC_IF_EOC: u8 = 0x80;
+// R8_ADC_CFG
+const RB_ADC_BUF_EN: u8 = 0x02;
+const RB_ADC_DIFF_EN: u8 = 0x04;
+const RB_ADC_OFS_TEST: u8 = 0x08;
+
+static EOC_WAKER: AtomicWaker = AtomicWaker::new();
+static EOC_CALLBACK: Mutex<Cell<Option<fn(u16)>>> = Mutex::new(Cell::new(None));
+
+/// ADC error
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Sample rate out of `Fsys / 4096` to `Fsys / 16`
+    InvalidSampleRate,
+    /// Ring buffer was overwritten before it was read
+    Overrun,
+}
+
 /// Sampling clock
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
 pub enum SamplingClock {
@@ -27,12 +63,28 @@ pub enum Gain {
     GAIN2 = 0b11,
 }
 
+/// Number of conversions averaged per sample
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Oversampling {
+    #[default]
+    X1 = 0,
+    X2 = 1,
+    X4 = 2,
+    X8 = 3,
+    X16 = 4,
+    X32 = 5,
+    X64 = 6,
+}
+
 #[non_exhaustive]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Config {
     pub clk: SamplingClock,
     pub pga_gain: Gain,
     pub diff_en: bool,
+    /// Reference voltage in millivolts, 1050 typical
+    pub vref_mv: u16,
+    pub oversampling: Oversampling,
 }
 
 impl Default for Config {
@@ -41,6 +93,8 @@ impl Default for Config {
             clk: SamplingClock::_3_2MHz,
             pga_gain: Gain::GAIN1_2,
             diff_en: false,
+            vref_mv: 1050,
+            oversampling: Oversampling::X1,
         }
     }
 }
@@ -51,6 +105,7 @@ impl Config {
             clk: SamplingClock::_3_2MHz,
             pga_gain: Gain::GAIN2,
             diff_en: true,
+            ..Default::default()
         }
     }
 
@@ -59,6 +114,7 @@ impl Config {
             clk: SamplingClock::_3_2MHz,
             pga_gain: Gain::GAIN1_4,
             diff_en: false,
+            ..Default::default()
         }
     }
 }
@@ -66,6 +122,9 @@ impl Config {
 pub struct Adc<'d, T: Instance> {
     #[allow(unused)]
     adc: crate::PeripheralRef<'d, T>,
+    config: Config,
+    /// Added to every sample, from [`Adc::calibrate`]
+    offset: i16,
 }
 
 pub(crate) mod sealed {
@@ -85,9 +144,11 @@ pub(crate) mod sealed {
     pub trait InternalChannel<T> {
         fn channel(&self) -> u8;
     }
+
+    pub trait DifferentialPin<T: Instance, N> {}
 }
 
-pub trait Instance: sealed::Instance + crate::Peripheral<P = Self> {}
+pub trait Instance: sealed::Instance + crate::Peripheral<P = Self> + crate::rcc::RccPeripheral {}
 pub trait AdcPin<T: Instance>: sealed::AdcPin<T> {}
 pub trait InternalChannel<T>: sealed::InternalChannel<T> {}
 
@@ -116,20 +177,67 @@ macro_rules! impl_adc_pin {
     };
 }
 
-impl_adc_pin!(ADC, PA7, 11);
-impl_adc_pin!(ADC, PA8, 12);
-impl_adc_pin!(ADC, PA9, 13);
+// Channel table, PA0-PA3 and PA6-PA9 are not bonded out on the `ch582` and `ch581` QFN28 packages
 impl_adc_pin!(ADC, PA4, 0);
 impl_adc_pin!(ADC, PA5, 1);
-impl_adc_pin!(ADC, PA6, 2);
-impl_adc_pin!(ADC, PA0, 9);
-impl_adc_pin!(ADC, PA1, 8);
-impl_adc_pin!(ADC, PA2, 7);
-impl_adc_pin!(ADC, PA3, 6);
-impl_adc_pin!(ADC, PA15, 5);
-impl_adc_pin!(ADC, PA14, 4);
-impl_adc_pin!(ADC, PA13, 3);
 impl_adc_pin!(ADC, PA12, 2);
+impl_adc_pin!(ADC, PA13, 3);
+impl_adc_pin!(ADC, PA14, 4);
+impl_adc_pin!(ADC, PA15, 5);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+impl_adc_pin!(ADC, PA3, 6);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+impl_adc_pin!(ADC, PA2, 7);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+impl_adc_pin!(ADC, PA1, 8);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+impl_adc_pin!(ADC, PA0, 9);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+impl_adc_pin!(ADC, PA6, 10);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+impl_adc_pin!(ADC, PA7, 11);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+impl_adc_pin!(ADC, PA8, 12);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+impl_adc_pin!(ADC, PA9, 13);
+
+/// Positive input of a differential pair, with `N` as the negative input
+pub trait DifferentialPin<T: Instance, N: AdcPin<T>>: AdcPin<T> + sealed::DifferentialPin<T, N> {}
+
+macro_rules! impl_diff_pin {
+    ($inst:ident, $p:ident, $n:ident) => {
+        impl sealed::DifferentialPin<peripherals::$inst, peripherals::$n> for peripherals::$p {}
+        impl DifferentialPin<peripherals::$inst, peripherals::$n> for peripherals::$p {}
+    };
+}
+
+// In differential mode, channel 0 is AIN0 - AIN2 and channel 1 is AIN1 - AIN3
+impl_diff_pin!(ADC, PA4, PA12);
+impl_diff_pin!(ADC, PA5, PA13);
+
+/// Differential input, sampled as `P - N`
+pub struct DifferentialPair<'d, P, N> {
+    positive: PeripheralRef<'d, P>,
+    _negative: PeripheralRef<'d, N>,
+}
+
+impl<'d, P, N> DifferentialPair<'d, P, N>
+where
+    P: DifferentialPin<peripherals::ADC, N>,
+    N: AdcPin<peripherals::ADC>,
+{
+    pub fn new(positive: impl Peripheral<P = P> + 'd, negative: impl Peripheral<P = N> + 'd) -> Self {
+        into_ref!(positive, negative);
+
+        positive.set_as_analog();
+        negative.set_as_analog();
+
+        Self {
+            positive,
+            _negative: negative,
+        }
+    }
+}
 
 pub struct Temperature;
 impl AdcPin<peripherals::ADC> for Temperature {}
@@ -146,11 +254,85 @@ impl Temperature {
     }
 }
 
+/// Temperature sensor in milli-degrees Celsius
+pub trait ReadTemperature {
+    type Error: core::fmt::Debug;
+
+    fn read_milli_celsius(&mut self) -> Result<i32, Self::Error>;
+}
+
+/// On-chip temperature sensor, calibrated by the factory value in ROM.
+///
+/// Switches the ADC to [`Config::for_temperature`] for each reading and restores the previous config.
+pub struct TemperatureSensor<'a, 'd> {
+    adc: &'a mut Adc<'d, peripherals::ADC>,
+    channel: Temperature,
+    samples: u8,
+}
+
+impl<'a, 'd> TemperatureSensor<'a, 'd> {
+    pub fn new(adc: &'a mut Adc<'d, peripherals::ADC>) -> Self {
+        let channel = adc.enable_temperature();
+        crate::delay_us(Temperature::start_time_us() as u16);
+
+        Self {
+            adc,
+            channel,
+            samples: 8,
+        }
+    }
+
+    /// Number of conversions averaged per reading, 8 by default
+    pub fn set_samples(&mut self, samples: u8) {
+        self.samples = samples.max(1);
+    }
+
+    /// Averaged raw sample, without offset calibration as the ROM value is raw too
+    pub fn read_raw(&mut self) -> u16 {
+        let saved = self.adc.config();
+        let saved_offset = self.adc.offset();
+        self.adc.set_config(Config {
+            vref_mv: saved.vref_mv,
+            ..Config::for_temperature()
+        });
+
+        let mut sum = 0;
+        for _ in 0..self.samples {
+            sum += self.adc.read_raw(&mut self.channel) as u32;
+        }
+
+        // set_config clears the offset, so it must be restored after the config
+        self.adc.set_config(saved);
+        self.adc.set_offset(saved_offset);
+        ((sum + self.samples as u32 / 2) / self.samples as u32) as u16
+    }
+
+    pub fn read_milli_celsius(&mut self) -> i32 {
+        adc_to_temperature_milli_celsius(self.read_raw())
+    }
+}
+
+impl<'a, 'd> Drop for TemperatureSensor<'a, 'd> {
+    fn drop(&mut self) {
+        <peripherals::ADC as sealed::Instance>::regs()
+            .tem_sensor
+            .modify(|_, w| w.power_on().clear_bit());
+    }
+}
+
+impl<'a, 'd> ReadTemperature for TemperatureSensor<'a, 'd> {
+    type Error = core::convert::Infallible;
+
+    fn read_milli_celsius(&mut self) -> Result<i32, Self::Error> {
+        Ok(TemperatureSensor::read_milli_celsius(self))
+    }
+}
+
 pub struct Vbat;
 impl AdcPin<peripherals::ADC> for Vbat {}
 impl sealed::AdcPin<peripherals::ADC> for Vbat {
     fn channel(&self) -> u8 {
-        15
+        14
     }
 }
 
@@ -161,6 +343,8 @@ where
     pub fn new(adc: impl Peripheral<P = T> + 'd, config: Config) -> Self {
         into_ref!(adc);
 
+        <T as crate::rcc::sealed::RccPeripheral>::enable();
+
         let rb = T::regs();
         rb.cfg.modify(|_, w| {
             w.power_on()
@@ -187,10 +371,20 @@ where
                 .variant(0b00)
         }); */
 
-        Self { adc }
+        let mut this = Self { adc, config, offset: 0 };
+        this.calibrate();
+        this
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
     }
 
-    pub fn set_config(&self, config: Config) {
+    /// Apply a new config. The offset depends on gain and clock, so it is cleared:
+    /// call [`Adc::calibrate`] again before reading calibrated samples.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.offset = 0;
         let rb = T::regs();
         rb.cfg.modify(|_, w| {
             w.diff_en()
@@ -229,40 +423,338 @@ where
         rb.data.read().data().bits()
     }
 
-    pub fn read(&mut self, pin: &mut impl AdcPin<T>) -> u16 {
+    /// Measure the ADC offset with the PGA input shorted, applied to every following sample.
+    ///
+    /// Called by [`Adc::new`], call again after [`Adc::set_config`] or a large temperature change.
+    pub fn calibrate(&mut self) -> i16 {
         let rb = T::regs();
 
-        pin.set_as_analog();
+        let channel = rb.channel.read().bits();
+        let cfg = rb.cfg.read().bits();
+
+        // offset test mode, with the configured gain and clock
+        rb.channel.write(|w| unsafe { w.bits(1) });
+        rb.cfg.modify(|_, w| unsafe { w.bits(cfg | RB_ADC_OFS_TEST) });
+        // first conversion is discarded
+        self.convert();
+        let mut sum = 0;
+        for _ in 0..16 {
+            // the offset test inverts data
+            sum += (!self.convert() & 0xfff) as u32;
+        }
+        let avg = (sum + 8) >> 4;
+
+        rb.cfg.write(|w| unsafe { w.bits(cfg) });
+        rb.channel.write(|w| unsafe { w.bits(channel) });
 
-        // Configure ADC
-        let channel = pin.channel();
+        self.offset = 2048 - avg as i16;
+        self.offset
+    }
+
+    pub fn offset(&self) -> i16 {
+        self.offset
+    }
 
-        // Select channel
-        rb.channel.modify(|_, w| w.ch_idx().variant(channel));
+    /// Restore an offset measured earlier by [`Adc::calibrate`] with the current config
+    pub(crate) fn set_offset(&mut self, offset: i16) {
+        self.offset = offset;
+    }
 
+    /// Single conversion, no offset and no oversampling
+    pub fn read_raw(&mut self, pin: &mut impl AdcPin<T>) -> u16 {
+        self.select(pin);
         self.convert()
     }
 
-    /// Read ADC sample data as millivolts. Avoid using soft-fp, about 20k flash increase.
-    pub fn read_as_millivolts(&mut self, pin: &mut impl AdcPin<T>) -> i32 {
+    /// Sample with oversampling, offset calibrated
+    pub fn read(&mut self, pin: &mut impl AdcPin<T>) -> u16 {
+        self.select(pin);
+        self.sample()
+    }
+
+    /// Sample a differential pair as `P - N`, oversampled and offset calibrated.
+    ///
+    /// Results are signed around mid-scale, from -2048 to 2047.
+    pub fn read_differential<P, N>(&mut self, pair: &mut DifferentialPair<'_, P, N>) -> i16
+    where
+        P: DifferentialPin<T, N>,
+        N: AdcPin<T>,
+    {
         let rb = T::regs();
 
+        let cfg = rb.cfg.read().bits();
+        rb.cfg
+            .write(|w| unsafe { w.bits((cfg | RB_ADC_DIFF_EN) & !RB_ADC_BUF_EN) });
+        rb.channel.modify(|_, w| w.ch_idx().variant(pair.positive.channel()));
+        let data = self.sample();
+        rb.cfg.write(|w| unsafe { w.bits(cfg) });
+
+        data as i16 - 2048
+    }
+
+    /// Convert a differential sample to millivolts, with the configured gain and reference
+    pub fn differential_to_millivolts(&self, data: i16) -> i32 {
+        let vref = self.config.vref_mv as i32;
+        let data = data as i32;
+        match self.config.pga_gain {
+            Gain::GAIN1_4 => data * vref / 512,
+            Gain::GAIN1_2 => data * vref / 1024,
+            Gain::GAIN1 => data * vref / 2048,
+            Gain::GAIN2 => data * vref / 4096,
+        }
+    }
+
+    /// Oversampled and offset calibrated conversion of the selected channel
+    fn sample(&mut self) -> u16 {
+        let shift = self.config.oversampling as u32;
+        let mut sum = 0;
+        for _ in 0..(1u32 << shift) {
+            sum += self.convert() as u32;
+        }
+        let data = (sum + ((1 << shift) >> 1)) >> shift;
+
+        self.calibrated(data as u16)
+    }
+
+    /// Read ADC sample data as millivolts, offset calibrated. Avoid using soft-fp, about 20k flash increase.
+    pub fn read_as_millivolts(&mut self, pin: &mut impl AdcPin<T>) -> i32 {
         let data = self.read(pin);
+        self.to_millivolts(data)
+    }
 
-        let vref = 1050;
+    /// Convert sample data to millivolts, with the configured gain and reference
+    pub fn to_millivolts(&self, data: u16) -> i32 {
+        let vref = self.config.vref_mv as i32;
+        let data = data as i32;
         // Ref: DS manual
-        match rb.cfg.read().pga_gain().bits() {
+        match self.config.pga_gain {
             // -12dB, 1/4
-            0b00 => (data as i32) * vref / 512 - 3 * vref,
+            Gain::GAIN1_4 => data * vref / 512 - 3 * vref,
             // -6dB, 1/2
-            0b01 => (data as i32) * vref / 1024 - 1 * vref,
+            Gain::GAIN1_2 => data * vref / 1024 - vref,
             // 0dB, 1
-            0b10 => (data as i32) * vref / 2048,
+            Gain::GAIN1 => data * vref / 2048,
             // 6dB, 2
-            0b11 => (data as i32) * vref / 4096 + 1050 / 2,
-            _ => unreachable!(),
+            Gain::GAIN2 => data * vref / 4096 + vref / 2,
         }
     }
+
+    fn calibrated(&self, data: u16) -> u16 {
+        (data as i32 + self.offset as i32).clamp(0, 0xfff) as u16
+    }
+}
+
+impl<'d> Adc<'d, peripherals::ADC> {
+    /// Calibrated temperature sensor, borrowing the ADC
+    pub fn temperature_sensor(&mut self) -> TemperatureSensor<'_, 'd> {
+        TemperatureSensor::new(self)
+    }
+}
+
+impl<'d, T: Instance> Adc<'d, T> {
+    /// Sample `pin` at `rate` into `buf` by DMA, blocking until `buf` is full.
+    ///
+    /// The automatic sampling period is `(256 - cycle) * 16` Fsys cycles, e.g. 14.6kHz to 3.75MHz at 60MHz.
+    /// The ADC conversion time limits the highest usable rate. Samples are raw, see [`Adc::offset`].
+    pub fn read_continuous(&mut self, pin: &mut impl AdcPin<T>, rate: Hertz, buf: &mut [u16]) -> Result<(), Error> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let cycle = auto_cycle(rate)?;
+        self.select(pin);
+
+        start_dma::<T>(buf, false, cycle);
+        while T::regs().dma_if.read().bits() & RB_ADC_IF_DMA_END == 0 {}
+        stop_dma::<T>();
+
+        Ok(())
+    }
+
+    /// Sample `pin` at `rate` into `buf` continuously, as a ring buffer.
+    pub fn start_ring_buffer<'a>(
+        &'a mut self,
+        pin: &mut impl AdcPin<T>,
+        rate: Hertz,
+        buf: &'a mut [u16],
+    ) -> Result<RingBuffer<'a, T>, Error> {
+        assert!(!buf.is_empty());
+        let cycle = auto_cycle(rate)?;
+        self.select(pin);
+
+        start_dma::<T>(buf, true, cycle);
+
+        Ok(RingBuffer {
+            _adc: core::marker::PhantomData,
+            buf,
+            read_idx: 0,
+        })
+    }
+
+    /// Convert once, awaiting the end-of-conversion interrupt.
+    ///
+    /// [`Adc::on_interrupt`] must be called from the `ADC` handler.
+    pub async fn read_async(&mut self, pin: &mut impl AdcPin<T>) -> u16 {
+        let rb = T::regs();
+        self.select(pin);
+
+        rb.ctrl_dma.modify(|r, w| unsafe { w.bits(r.bits() | RB_ADC_IE_EOC) });
+        unsafe { T::Interrupt::enable() };
+        rb.convert.modify(|_, w| w.start().set_bit());
+
+        poll_fn(|cx| {
+            EOC_WAKER.register(cx.waker());
+
+            if rb.convert.read().start().bit_is_set() {
+                Poll::Pending
+            } else {
+                Poll::Ready(self.calibrated(rb.data.read().data().bits()))
+            }
+        })
+        .await
+    }
+
+    /// Convert continuously, calling `callback` from the `ADC` interrupt with every sample.
+    ///
+    /// Samples are raw, add [`Adc::offset`] for calibrated values.
+    ///
+    /// [`Adc::on_interrupt`] must be called from the `ADC` handler.
+    pub fn start_with_callback(&mut self, pin: &mut impl AdcPin<T>, callback: fn(u16)) {
+        let rb = T::regs();
+        self.select(pin);
+
+        critical_section::with(|cs| EOC_CALLBACK.borrow(cs).set(Some(callback)));
+        rb.ctrl_dma
+            .modify(|r, w| unsafe { w.bits(r.bits() | RB_ADC_IE_EOC | RB_ADC_CONT_EN) });
+        unsafe { T::Interrupt::enable() };
+        rb.convert.modify(|_, w| w.start().set_bit());
+    }
+
+    pub fn stop_callback(&mut self) {
+        let rb = T::regs();
+        rb.ctrl_dma
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(RB_ADC_IE_EOC | RB_ADC_CONT_EN)) });
+        rb.convert.write(|w| unsafe { w.bits(0) });
+        critical_section::with(|cs| EOC_CALLBACK.borrow(cs).set(None));
+    }
+
+    /// Call this in the `ADC` IRQ handler.
+    ///
+    /// Wakes [`Adc::read_async`], or calls the callback of [`Adc::start_with_callback`].
+    pub fn on_interrupt() {
+        let rb = T::regs();
+        if rb.int_flag.read().bits() & RB_ADC_IF_EOC == 0 {
+            return;
+        }
+
+        if let Some(callback) = critical_section::with(|cs| EOC_CALLBACK.borrow(cs).get()) {
+            let data = rb.data.read().data().bits();
+            // clear flag
+            rb.convert.write(|w| unsafe { w.bits(0) });
+            callback(data);
+        } else {
+            // one-shot, the future reads data
+            rb.ctrl_dma.modify(|r, w| unsafe { w.bits(r.bits() & !RB_ADC_IE_EOC) });
+            EOC_WAKER.wake();
+        }
+    }
+
+    fn select(&mut self, pin: &mut impl AdcPin<T>) {
+        pin.set_as_analog();
+        T::regs().channel.modify(|_, w| w.ch_idx().variant(pin.channel()));
+    }
+}
+
+/// ADC samples in a circular DMA buffer, stops sampling on drop.
+pub struct RingBuffer<'a, T: Instance> {
+    _adc: core::marker::PhantomData<&'a mut T>,
+    buf: &'a mut [u16],
+    read_idx: usize,
+}
+
+impl<'a, T: Instance> RingBuffer<'a, T> {
+    /// Index the DMA writes next
+    fn write_idx(&self) -> usize {
+        let beg = self.buf.as_ptr() as u32 as u16;
+        let now = T::regs().dma_now.read().bits();
+        (now.wrapping_sub(beg) / 2) as usize % self.buf.len()
+    }
+
+    /// Number of samples ready to read
+    pub fn len(&self) -> usize {
+        let write = self.write_idx();
+        (write + self.buf.len() - self.read_idx) % self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read available samples into `out`, returns number of samples read.
+    ///
+    /// Fails with [`Error::Overrun`] when DMA lapped the reader, the buffer is reset then.
+    pub fn read(&mut self, out: &mut [u16]) -> Result<usize, Error> {
+        let rb = T::regs();
+        let n = self.buf.len();
+
+        let write = self.write_idx();
+        let wrapped = rb.dma_if.read().bits() & RB_ADC_IF_DMA_END != 0;
+        if wrapped {
+            rb.dma_if.write(|w| unsafe { w.bits(RB_ADC_IF_DMA_END) });
+            if write >= self.read_idx {
+                self.read_idx = write;
+                return Err(Error::Overrun);
+            }
+        }
+
+        let available = (write + n - self.read_idx) % n;
+        let count = available.min(out.len());
+        for o in out[..count].iter_mut() {
+            // DMA writes behind our back
+            *o = unsafe { ptr::read_volatile(&self.buf[self.read_idx]) };
+            self.read_idx = (self.read_idx + 1) % n;
+        }
+        Ok(count)
+    }
+}
+
+impl<'a, T: Instance> Drop for RingBuffer<'a, T> {
+    fn drop(&mut self) {
+        stop_dma::<T>();
+    }
+}
+
+/// `R8_ADC_AUTO_CYCLE` for `rate`
+fn auto_cycle(rate: Hertz) -> Result<u8, Error> {
+    let hclk = crate::sysctl::clocks().hclk.to_Hz();
+    let periods = hclk / 16 / rate.to_Hz().max(1);
+    if !(1..=256).contains(&periods) {
+        return Err(Error::InvalidSampleRate);
+    }
+    Ok((256 - periods) as u8)
+}
+
+/// DMA address registers are 16-bit offsets into RAM, a `&mut [u16]` is always in RAM and aligned
+fn start_dma<T: Instance>(buf: &mut [u16], circular: bool, cycle: u8) {
+    let addr = buf.as_mut_ptr() as u32;
+    let end = addr + (buf.len() as u32) * 2;
+
+    let rb = T::regs();
+    rb.auto_cycle.write(|w| unsafe { w.bits(cycle) });
+    rb.dma_beg.write(|w| unsafe { w.bits(addr as u16) });
+    rb.dma_end.write(|w| unsafe { w.bits(end as u16) });
+    rb.dma_if.write(|w| unsafe { w.bits(RB_ADC_IF_DMA_END) });
+
+    let mut ctrl = RB_ADC_DMA_ENABLE | RB_ADC_AUTO_EN;
+    if circular {
+        ctrl |= RB_ADC_DMA_LOOP;
+    }
+    rb.ctrl_dma.write(|w| unsafe { w.bits(ctrl) });
+}
+
+fn stop_dma<T: Instance>() {
+    let rb = T::regs();
+    rb.ctrl_dma.write(|w| unsafe { w.bits(0) });
+    rb.dma_if.write(|w| unsafe { w.bits(RB_ADC_IF_DMA_END) });
 }
 
 impl<'d, T: Instance> Drop for Adc<'d, T> {
@@ -280,7 +772,7 @@ pub fn adc_to_temperature_celsius(data: u16) -> i32 {
     //  temp = (((C25 >> 16) & 0xFFFF) ? ((C25 >> 16) & 0xFFFF) : 25) + \
     // (adc_val - ((int)(C25 & 0xFFFF))) * 10 / 27;
     let c25_ = ((c25 >> 16) & 0xFFFF) as i32;
-    let c25_ = if c25 != 0 { c25_ } else { 25 };
+    let c25_ = if c25_ != 0 { c25_ } else { 25 };
     c25_ + ((data as i32) - ((c25 & 0xFFFF) as i32)) * 10 / 27
 }
 
@@ -289,6 +781,6 @@ pub fn adc_to_temperature_milli_celsius(data: u16) -> i32 {
     let c25 = unsafe { ptr::read_volatile(ROM_CFG_TMP_25C) };
 
     let c25_ = ((c25 >> 16) & 0xFFFF) as i32;
-    let c25_ = if c25 != 0 { c25_ } else { 25 };
+    let c25_ = if c25_ != 0 { c25_ } else { 25 };
     c25_ * 1000 + ((data as i32) - ((c25 & 0xFFFF) as i32)) * 10_000 / 27
 }
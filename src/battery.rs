@@ -0,0 +1,223 @@
+//! Battery voltage monitor
+//!
+//! The detector compares VDD against a threshold, and raises the `WDOG_BAT` interrupt when low.
+//! There's no hardware reset on low voltage, [`BatteryMonitor::on_interrupt`] emulates it.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::adc::{self, Adc, Vbat};
+use crate::interrupt::Interrupt;
+use crate::peripherals::{ADC, BAT};
+use crate::{into_ref, pac, with_safe_access, Peripheral, PeripheralRef};
+
+// R8_BAT_DET_CTRL
+const BAT_DET_EN: u8 = 0x01;
+const BAT_MON_EN: u8 = 0x02;
+const BAT_LOWER_IE: u8 = 0x04;
+const BAT_LOW_IE: u8 = 0x08;
+// R8_BAT_DET_CFG
+const BAT_LOW_VTH: u8 = 0x03;
+// R8_BAT_STATUS
+const BAT_STAT_LOWER: u8 = 0x01;
+const BAT_STAT_LOW: u8 = 0x02;
+
+static RESET_ON_LOW: AtomicBool = AtomicBool::new(false);
+
+/// Detection threshold
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Threshold {
+    /// Low power monitor, also works in halt and sleep modes
+    LowPower1V8 = 0x00,
+    LowPower1V9 = 0x01,
+    LowPower2V0 = 0x02,
+    LowPower2V1 = 0x03,
+    /// High accuracy detector
+    HighAccuracy1V9 = 0x04,
+    HighAccuracy2V1 = 0x05,
+    HighAccuracy2V3 = 0x06,
+    HighAccuracy2V5 = 0x07,
+}
+
+impl Threshold {
+    fn ctrl(self) -> u8 {
+        if self as u8 & 0x04 != 0 {
+            BAT_DET_EN
+        } else {
+            BAT_MON_EN
+        }
+    }
+
+    fn cfg(self) -> u8 {
+        self as u8 & BAT_LOW_VTH
+    }
+}
+
+/// Battery monitor events
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Events {
+    /// Below the threshold
+    pub low: bool,
+    /// Below the lower threshold, about 0.1V under the threshold
+    pub lower: bool,
+}
+
+pub struct BatteryMonitor<'d> {
+    _inner: PeripheralRef<'d, BAT>,
+}
+
+impl<'d> BatteryMonitor<'d> {
+    pub fn new(p: impl Peripheral<P = BAT> + 'd, threshold: Threshold) -> Self {
+        into_ref!(p);
+
+        let mut this = Self { _inner: p };
+        this.set_threshold(threshold);
+        this
+    }
+
+    pub fn set_threshold(&mut self, threshold: Threshold) {
+        let ie = sys().bat_det_ctrl.read().bits() & (BAT_LOW_IE | BAT_LOWER_IE);
+        with_safe_access(|| unsafe {
+            sys().bat_det_ctrl.write(|w| w.bits(threshold.ctrl()));
+            sys().bat_det_cfg.write(|w| w.bits(threshold.cfg()));
+        });
+        // detector settles before interrupts are enabled again
+        crate::delay_us(1);
+        if ie != 0 {
+            with_safe_access(|| unsafe {
+                sys().bat_det_ctrl.write(|w| w.bits(threshold.ctrl() | ie));
+            });
+        }
+    }
+
+    pub fn is_low(&self) -> bool {
+        sys().bat_status.read().bits() & BAT_STAT_LOW != 0
+    }
+
+    pub fn is_lower(&self) -> bool {
+        sys().bat_status.read().bits() & BAT_STAT_LOWER != 0
+    }
+
+    /// Enable the `WDOG_BAT` interrupt on low and lower voltage.
+    ///
+    /// The flags are levels, the handler disables the interrupt of an asserted flag.
+    pub fn enable_interrupt(&mut self) {
+        with_safe_access(|| unsafe {
+            sys()
+                .bat_det_ctrl
+                .modify(|r, w| w.bits(r.bits() | BAT_LOW_IE | BAT_LOWER_IE));
+        });
+        unsafe { crate::interrupt::WDOG_BAT::enable() };
+    }
+
+    pub fn disable_interrupt(&mut self) {
+        with_safe_access(|| unsafe {
+            sys()
+                .bat_det_ctrl
+                .modify(|r, w| w.bits(r.bits() & !(BAT_LOW_IE | BAT_LOWER_IE)));
+        });
+    }
+
+    /// Reset the chip from [`BatteryMonitor::on_interrupt`] when the lower threshold is crossed
+    pub fn set_reset_on_low(&mut self, enable: bool) {
+        RESET_ON_LOW.store(enable, Ordering::Relaxed);
+    }
+
+    /// Call this in the `WDOG_BAT` IRQ handler, shared with the watchdog.
+    ///
+    /// Disables the interrupt of the asserted flags, re-enable it with [`BatteryMonitor::enable_interrupt`].
+    pub fn on_interrupt() -> Events {
+        let status = sys().bat_status.read().bits();
+        let ctrl = sys().bat_det_ctrl.read().bits();
+
+        let events = Events {
+            low: status & BAT_STAT_LOW != 0 && ctrl & BAT_LOW_IE != 0,
+            lower: status & BAT_STAT_LOWER != 0 && ctrl & BAT_LOWER_IE != 0,
+        };
+
+        if events.lower && RESET_ON_LOW.load(Ordering::Relaxed) {
+            crate::software_reset();
+        }
+
+        let mut mask = 0;
+        if events.low {
+            mask |= BAT_LOW_IE;
+        }
+        if events.lower {
+            mask |= BAT_LOWER_IE;
+        }
+        if mask != 0 {
+            with_safe_access(|| unsafe {
+                sys().bat_det_ctrl.write(|w| w.bits(ctrl & !mask));
+            });
+        }
+
+        events
+    }
+
+    /// Measure VBAT with the ADC, in millivolts.
+    ///
+    /// Calibrates the ADC for the VBAT gain, its config and offset are restored afterwards.
+    pub fn millivolts(&self, adc: &mut Adc<'_, ADC>) -> i32 {
+        let saved = adc.config();
+        let saved_offset = adc.offset();
+
+        adc.set_config(adc::Config {
+            vref_mv: saved.vref_mv,
+            oversampling: saved.oversampling,
+            ..adc::Config::for_vbat()
+        });
+        adc.calibrate();
+        let mv = adc.read_as_millivolts(&mut Vbat);
+
+        adc.set_config(saved);
+        adc.set_offset(saved_offset);
+        mv
+    }
+
+    /// Estimated state of charge of a CR2032 coin cell, in percent
+    pub fn state_of_charge(&self, adc: &mut Adc<'_, ADC>) -> u8 {
+        coin_cell_state_of_charge(self.millivolts(adc))
+    }
+}
+
+impl<'d> Drop for BatteryMonitor<'d> {
+    fn drop(&mut self) {
+        with_safe_access(|| unsafe {
+            sys().bat_det_ctrl.write(|w| w.bits(0));
+        });
+    }
+}
+
+fn sys() -> &'static pac::sys::RegisterBlock {
+    unsafe { &*pac::SYS::PTR }
+}
+
+/// CR2032 discharge curve at light load, (millivolts, percent)
+const CR2032_CURVE: [(i32, u8); 9] = [
+    (3000, 100),
+    (2900, 80),
+    (2800, 60),
+    (2700, 40),
+    (2600, 30),
+    (2500, 20),
+    (2400, 10),
+    (2200, 5),
+    (2000, 0),
+];
+
+/// State of charge of a CR2032 coin cell from its voltage, interpolated on a typical discharge curve
+pub fn coin_cell_state_of_charge(millivolts: i32) -> u8 {
+    if millivolts >= CR2032_CURVE[0].0 {
+        return 100;
+    }
+    for w in CR2032_CURVE.windows(2) {
+        let (hi_mv, hi_pct) = w[0];
+        let (lo_mv, lo_pct) = w[1];
+        if millivolts >= lo_mv {
+            let pct = lo_pct as i32 + (millivolts - lo_mv) * (hi_pct - lo_pct) as i32 / (hi_mv - lo_mv);
+            return pct as u8;
+        }
+    }
+    0
+}
@@ -0,0 +1,369 @@
+//! A/B firmware update
+//!
+//! Code Flash is split into the bootloader at 0, the ACTIVE slot the application is linked to,
+//! the DFU slot receiving new images and a scratch block used while swapping the two slots.
+//!
+//! The application writes a new image into DFU with [`FirmwareUpdater`] and marks it for update.
+//! On the next boot, [`BootLoader::prepare`] swaps ACTIVE and DFU block by block. The new image
+//! has to call [`FirmwareUpdater::mark_booted`], otherwise the next boot swaps the old one back.
+//!
+//! Progress is kept in two alternating Data-Flash records, written once per swapped block, a swap
+//! resumes after power loss. Only the longer of the two images is swapped.
+
+use crate::flash::{self, CodeFlash, DataFlash};
+use crate::isp::{EEPROM_BLOCK_SIZE, EEPROM_MAX_SIZE, EEPROM_MIN_ERASE_SIZE, EEPROM_PAGE_SIZE, FLASH_ROM_MAX_SIZE};
+
+pub use self::swap::{crc32, Crc32, ImageHeader, State, IMAGE_MAGIC};
+use self::swap::{Record, Slots, SwapFlash, BLOCK_SIZE, RECORD_SIZE};
+
+mod swap;
+
+const PAGE_SIZE: usize = EEPROM_PAGE_SIZE as usize;
+const _: () = assert!(BLOCK_SIZE == EEPROM_BLOCK_SIZE);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    Flash(flash::Error),
+    /// Wrong magic, or the image doesn't fit the slot
+    InvalidHeader,
+    /// Image CRC32 doesn't match the header
+    Crc,
+    /// Write outside of the DFU slot
+    OutOfBounds,
+    /// Partitions not block aligned, overlapping or outside Code-Flash, slots of different sizes,
+    /// or state records outside Data-Flash
+    InvalidConfig,
+}
+
+impl From<flash::Error> for Error {
+    fn from(e: flash::Error) -> Self {
+        Error::Flash(e)
+    }
+}
+
+/// Range of Code Flash, aligned to 4KB blocks
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Partition {
+    pub from: u32,
+    pub to: u32,
+}
+
+impl Partition {
+    pub const fn new(from: u32, to: u32) -> Self {
+        Self { from, to }
+    }
+
+    pub const fn size(&self) -> u32 {
+        self.to - self.from
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BootConfig {
+    /// Slot the application is linked to, set `FLASH` origin in `memory.x` to match
+    pub active: Partition,
+    /// Same size as `active`
+    pub dfu: Partition,
+    /// At least one block
+    pub scratch: Partition,
+    /// Offset of two 256 byte blocks in Data-Flash for the update state
+    pub state: u32,
+}
+
+impl Default for BootConfig {
+    /// 32KB bootloader, 204KB slots, state at the end of Data-Flash
+    fn default() -> Self {
+        Self {
+            active: Partition::new(0x0000_8000, 0x0003_B000),
+            dfu: Partition::new(0x0003_B000, 0x0006_E000),
+            scratch: Partition::new(0x0006_E000, 0x0006_F000),
+            state: 0x7E00,
+        }
+    }
+}
+
+impl BootConfig {
+    /// Check the layout: block aligned, non-overlapping partitions in Code-Flash, slots of the same
+    /// size, scratch of at least one block and both state records in Data-Flash
+    pub fn validate(&self) -> Result<(), Error> {
+        let partitions = [self.active, self.dfu, self.scratch];
+        for (i, p) in partitions.iter().enumerate() {
+            if p.from % BLOCK_SIZE != 0 || p.to % BLOCK_SIZE != 0 || p.from >= p.to || p.to > FLASH_ROM_MAX_SIZE {
+                return Err(Error::InvalidConfig);
+            }
+            if partitions[i + 1..].iter().any(|q| p.from < q.to && q.from < p.to) {
+                return Err(Error::InvalidConfig);
+            }
+        }
+        if self.active.size() != self.dfu.size() || self.scratch.size() < BLOCK_SIZE {
+            return Err(Error::InvalidConfig);
+        }
+        let state_end = self.state.checked_add(2 * EEPROM_MIN_ERASE_SIZE);
+        if self.state % EEPROM_MIN_ERASE_SIZE != 0 || !state_end.is_some_and(|end| end <= EEPROM_MAX_SIZE) {
+            return Err(Error::InvalidConfig);
+        }
+        Ok(())
+    }
+
+    fn slots(&self) -> Slots {
+        Slots {
+            active: self.active.from,
+            dfu: self.dfu.from,
+            scratch: self.scratch.from,
+        }
+    }
+}
+
+/// Update state records, the latest valid one wins and the next write goes to the other block
+struct StateStore {
+    offset: u32,
+    current: Record,
+    slot: u32,
+}
+
+impl StateStore {
+    fn load(data: &mut DataFlash<'_>, offset: u32) -> Result<Self, Error> {
+        let mut this = Self {
+            offset,
+            current: Record::EMPTY,
+            slot: 1,
+        };
+        for slot in 0..2 {
+            let mut bytes = [0; RECORD_SIZE];
+            data.blocking_read(offset + slot * EEPROM_MIN_ERASE_SIZE, &mut bytes)?;
+            if let Some(record) = Record::from_bytes(&bytes) {
+                if record.seq >= this.current.seq {
+                    this.current = record;
+                    this.slot = slot;
+                }
+            }
+        }
+        Ok(this)
+    }
+
+    fn store(&mut self, data: &mut DataFlash<'_>, record: Record) -> Result<(), Error> {
+        let record = Record {
+            seq: self.current.seq.wrapping_add(1),
+            ..record
+        };
+        let slot = self.slot ^ 1;
+        let addr = self.offset + slot * EEPROM_MIN_ERASE_SIZE;
+        data.blocking_erase(addr, addr + EEPROM_MIN_ERASE_SIZE)?;
+        data.blocking_write(addr, &record.to_bytes())?;
+        self.current = record;
+        self.slot = slot;
+        Ok(())
+    }
+}
+
+fn copy_block(flash: &mut CodeFlash<'_>, from: u32, to: u32) -> Result<(), Error> {
+    let mut buf = [0; PAGE_SIZE];
+
+    flash.blocking_erase(to, to + BLOCK_SIZE)?;
+    for page in (0..BLOCK_SIZE).step_by(PAGE_SIZE) {
+        flash.blocking_read(from + page, &mut buf)?;
+        flash.blocking_write(to + page, &buf)?;
+    }
+    Ok(())
+}
+
+fn image_crc(flash: &mut CodeFlash<'_>, from: u32, len: u32) -> Result<u32, Error> {
+    let mut buf = [0; PAGE_SIZE];
+    let mut crc = Crc32::new();
+
+    let mut offset = 0;
+    while offset < len {
+        let n = (len - offset).min(PAGE_SIZE as u32) as usize;
+        flash.blocking_read(from + offset, &mut buf[..n])?;
+        crc.update(&buf[..n]);
+        offset += n as u32;
+    }
+    Ok(crc.finish())
+}
+
+/// Code-Flash blocks and the state records, for `swap::swap`
+struct Swapper<'a, 'd> {
+    code: &'a mut CodeFlash<'d>,
+    data: &'a mut DataFlash<'d>,
+    store: &'a mut StateStore,
+}
+
+impl SwapFlash for Swapper<'_, '_> {
+    type Error = Error;
+
+    fn copy_block(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        copy_block(self.code, from, to)
+    }
+
+    fn block_crc(&mut self, addr: u32) -> Result<u32, Error> {
+        image_crc(self.code, addr, BLOCK_SIZE)
+    }
+
+    fn store(&mut self, record: Record) -> Result<(), Error> {
+        self.store.store(self.data, record)
+    }
+}
+
+/// Receives a new image into the DFU slot, used by the application
+pub struct FirmwareUpdater<'d> {
+    code: CodeFlash<'d>,
+    data: DataFlash<'d>,
+    config: BootConfig,
+}
+
+impl<'d> FirmwareUpdater<'d> {
+    pub fn new(code: CodeFlash<'d>, data: DataFlash<'d>, config: BootConfig) -> Self {
+        Self { code, data, config }
+    }
+
+    pub fn get_state(&mut self) -> Result<State, Error> {
+        Ok(StateStore::load(&mut self.data, self.config.state)?.current.state)
+    }
+
+    /// Erase the DFU slot
+    pub fn prepare_update(&mut self) -> Result<(), Error> {
+        let dfu = self.config.dfu;
+        self.code.blocking_erase(dfu.from, dfu.to)?;
+        Ok(())
+    }
+
+    /// Write a chunk of the new image at `offset` in the DFU slot, dword aligned
+    pub fn write_firmware(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        let dfu = self.config.dfu;
+        if offset.saturating_add(data.len() as u32) > dfu.size() {
+            return Err(Error::OutOfBounds);
+        }
+        self.code.blocking_write(dfu.from + offset, data)?;
+        self.code.blocking_verify(dfu.from + offset, data)?;
+        Ok(())
+    }
+
+    /// Check the image in the DFU slot against `header`
+    pub fn verify(&mut self, header: &ImageHeader) -> Result<(), Error> {
+        let dfu = self.config.dfu;
+        if header.magic != IMAGE_MAGIC || header.len == 0 || header.len > dfu.size() {
+            return Err(Error::InvalidHeader);
+        }
+        if image_crc(&mut self.code, dfu.from, header.len)? != header.crc {
+            return Err(Error::Crc);
+        }
+        Ok(())
+    }
+
+    /// Verify the image and swap it in on the next boot.
+    ///
+    /// The swap covers the longer of the new image and the running one, whole slot if the running
+    /// image length is unknown.
+    pub fn mark_updated(&mut self, header: &ImageHeader) -> Result<(), Error> {
+        self.verify(header)?;
+
+        let mut store = StateStore::load(&mut self.data, self.config.state)?;
+        let slot = self.config.active.size();
+        let active_len = match store.current.state {
+            State::Boot if store.current.len != 0 => store.current.len.min(slot),
+            _ => slot,
+        };
+        let record = Record {
+            state: State::Swap,
+            progress: 0,
+            len: header.len.max(active_len),
+            header: *header,
+            ..store.current
+        };
+        store.store(&mut self.data, record)
+    }
+
+    /// Confirm the running image, call this once it's known to work
+    pub fn mark_booted(&mut self) -> Result<(), Error> {
+        let mut store = StateStore::load(&mut self.data, self.config.state)?;
+        if store.current.state == State::Swapped {
+            let record = Record {
+                state: State::Boot,
+                progress: 0,
+                len: store.current.header.len,
+                ..store.current
+            };
+            store.store(&mut self.data, record)?;
+        }
+        Ok(())
+    }
+}
+
+/// Swaps the slots and starts the application, runs from the bootloader partition
+pub struct BootLoader<'d> {
+    code: CodeFlash<'d>,
+    data: DataFlash<'d>,
+    config: BootConfig,
+}
+
+impl<'d> BootLoader<'d> {
+    /// Fails with [`Error::InvalidConfig`] if `config` doesn't pass [`BootConfig::validate`]
+    pub fn new(code: CodeFlash<'d>, data: DataFlash<'d>, config: BootConfig) -> Result<Self, Error> {
+        config.validate()?;
+
+        Ok(Self { code, data, config })
+    }
+
+    /// Finish or start a pending swap, or revert an unconfirmed image. Returns the state to boot with.
+    pub fn prepare(&mut self) -> Result<State, Error> {
+        let mut store = StateStore::load(&mut self.data, self.config.state)?;
+
+        match store.current.state {
+            State::Boot => {}
+            State::Swap => {
+                self.swap(&mut store)?;
+                self.set_state(&mut store, State::Swapped)?;
+            }
+            State::Swapped => {
+                // the new image didn't confirm, swap back
+                self.set_state(&mut store, State::Revert)?;
+                self.swap(&mut store)?;
+                self.set_state(&mut store, State::Boot)?;
+            }
+            State::Revert => {
+                self.swap(&mut store)?;
+                self.set_state(&mut store, State::Boot)?;
+            }
+        }
+
+        Ok(store.current.state)
+    }
+
+    /// Exchange ACTIVE and DFU through the scratch block, resuming from the stored progress
+    fn swap(&mut self, store: &mut StateStore) -> Result<(), Error> {
+        let record = store.current;
+        let mut flash = Swapper {
+            code: &mut self.code,
+            data: &mut self.data,
+            store,
+        };
+        swap::swap(&mut flash, self.config.slots(), record)
+    }
+
+    /// Store `state` with the progress reset, keeping the swap length
+    fn set_state(&mut self, store: &mut StateStore, state: State) -> Result<(), Error> {
+        let record = Record {
+            state,
+            progress: 0,
+            ..store.current
+        };
+        store.store(&mut self.data, record)
+    }
+
+    /// Jump to the ACTIVE slot.
+    ///
+    /// # Safety
+    ///
+    /// ACTIVE must hold an image linked to its address. Peripherals are left as configured.
+    pub unsafe fn load(self) -> ! {
+        let addr = self.config.active.from as usize;
+
+        riscv::interrupt::disable();
+        qingke::register::gintenr::write(0);
+        #[cfg(target_arch = "riscv32")]
+        core::arch::asm!("jr {0}", in(reg) addr, options(noreturn));
+        #[cfg(not(target_arch = "riscv32"))]
+        unimplemented!("jump to {:#x}", addr)
+    }
+}
@@ -0,0 +1,412 @@
+//! Image header, update state records and the block swap, free of flash access
+
+/// Image header magic, "CH58"
+pub const IMAGE_MAGIC: u32 = 0x3835_4843;
+const STATE_MAGIC: u32 = 0xB007_5747;
+
+/// Code-Flash erase block, `isp::EEPROM_BLOCK_SIZE`
+pub(crate) const BLOCK_SIZE: u32 = 4096;
+// magic, seq, state, progress, len, active_crc, dfu_crc, header, crc
+pub(crate) const RECORD_SIZE: usize = 44;
+
+/// Header sent along with an image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageHeader {
+    pub magic: u32,
+    pub len: u32,
+    /// CRC-32 (IEEE) of the `len` image bytes
+    pub crc: u32,
+}
+
+impl ImageHeader {
+    pub const SIZE: usize = 12;
+
+    pub fn new(image: &[u8]) -> Self {
+        Self {
+            magic: IMAGE_MAGIC,
+            len: image.len() as u32,
+            crc: crc32(image),
+        }
+    }
+
+    /// Little endian `magic`, `len`, `crc`
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let word = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Self {
+            magic: word(0),
+            len: word(4),
+            crc: word(8),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.len.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+}
+
+/// Incremental CRC-32 (IEEE 802.3)
+#[derive(Copy, Clone, Debug)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.0;
+        for &b in data {
+            crc ^= b as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        self.0 = crc;
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Update state
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum State {
+    /// Boot ACTIVE as is
+    Boot,
+    /// DFU holds a verified image, swap on next boot
+    Swap,
+    /// Swapped, waiting for the new image to confirm
+    Swapped,
+    /// The new image didn't confirm, swapping the old one back
+    Revert,
+}
+
+impl State {
+    fn from_u32(v: u32) -> Self {
+        match v {
+            1 => State::Swap,
+            2 => State::Swapped,
+            3 => State::Revert,
+            _ => State::Boot,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub(crate) seq: u32,
+    pub(crate) state: State,
+    /// `b + 1` once block `b` is copied to scratch, all blocks before `b` are swapped
+    pub(crate) progress: u32,
+    /// In `Boot`, length of the ACTIVE image, at most, 0 if unknown. Otherwise bytes to swap.
+    pub(crate) len: u32,
+    /// CRC of the block in progress in ACTIVE before the swap, i.e. of scratch
+    pub(crate) active_crc: u32,
+    /// CRC of the block in progress in DFU before the swap
+    pub(crate) dfu_crc: u32,
+    pub(crate) header: ImageHeader,
+}
+
+impl Record {
+    pub(crate) const EMPTY: Record = Record {
+        seq: 0,
+        state: State::Boot,
+        progress: 0,
+        len: 0,
+        active_crc: 0,
+        dfu_crc: 0,
+        header: ImageHeader {
+            magic: 0,
+            len: 0,
+            crc: 0,
+        },
+    };
+
+    pub(crate) fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..4].copy_from_slice(&STATE_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        bytes[8..12].copy_from_slice(&(self.state as u32).to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.progress.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.len.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.active_crc.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.dfu_crc.to_le_bytes());
+        bytes[28..40].copy_from_slice(&self.header.to_bytes());
+        let crc = crc32(&bytes[..40]);
+        bytes[40..44].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Option<Self> {
+        let word = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        if word(0) != STATE_MAGIC || word(40) != crc32(&bytes[..40]) {
+            return None;
+        }
+        let mut header = [0; ImageHeader::SIZE];
+        header.copy_from_slice(&bytes[28..40]);
+        Some(Self {
+            seq: word(4),
+            state: State::from_u32(word(8)),
+            progress: word(12),
+            len: word(16),
+            active_crc: word(20),
+            dfu_crc: word(24),
+            header: ImageHeader::from_bytes(&header),
+        })
+    }
+}
+
+/// Start addresses of the slots
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Slots {
+    pub(crate) active: u32,
+    pub(crate) dfu: u32,
+    pub(crate) scratch: u32,
+}
+
+/// Flash access of a swap
+pub(crate) trait SwapFlash {
+    type Error;
+
+    /// Erase the block at `to` and copy the block at `from` into it
+    fn copy_block(&mut self, from: u32, to: u32) -> Result<(), Self::Error>;
+
+    fn block_crc(&mut self, addr: u32) -> Result<u32, Self::Error>;
+
+    /// Persist `record` as the latest state
+    fn store(&mut self, record: Record) -> Result<(), Self::Error>;
+}
+
+/// Exchange the first `record.len` bytes of ACTIVE and DFU through scratch, resuming from
+/// `record.progress`.
+///
+/// One record is written per block, once the ACTIVE block is saved to scratch. On resume, the CRCs
+/// of the block in progress tell which of its two remaining copies are done.
+pub(crate) fn swap<F: SwapFlash>(flash: &mut F, slots: Slots, mut record: Record) -> Result<(), F::Error> {
+    let blocks = record.len.div_ceil(BLOCK_SIZE);
+
+    if record.progress > 0 {
+        let offset = (record.progress - 1) * BLOCK_SIZE;
+        // DFU is only overwritten once ACTIVE holds its copy
+        if flash.block_crc(slots.active + offset)? != record.dfu_crc {
+            flash.copy_block(slots.dfu + offset, slots.active + offset)?;
+        }
+        if flash.block_crc(slots.dfu + offset)? != record.active_crc {
+            flash.copy_block(slots.scratch, slots.dfu + offset)?;
+        }
+    }
+
+    for block in record.progress..blocks {
+        let offset = block * BLOCK_SIZE;
+        record.active_crc = flash.block_crc(slots.active + offset)?;
+        record.dfu_crc = flash.block_crc(slots.dfu + offset)?;
+        flash.copy_block(slots.active + offset, slots.scratch)?;
+        record.progress = block + 1;
+        flash.store(record)?;
+
+        flash.copy_block(slots.dfu + offset, slots.active + offset)?;
+        flash.copy_block(slots.scratch, slots.dfu + offset)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOT_BLOCKS: u32 = 4;
+    const SLOTS: Slots = Slots {
+        active: 0,
+        dfu: SLOT_BLOCKS * BLOCK_SIZE,
+        scratch: 2 * SLOT_BLOCKS * BLOCK_SIZE,
+    };
+
+    #[derive(Debug)]
+    struct PowerLoss;
+
+    /// Flash losing power after `ops_left` copies or stores, half way through a copy
+    struct MockFlash {
+        mem: Vec<u8>,
+        record: Record,
+        ops_left: usize,
+    }
+
+    impl MockFlash {
+        fn new(record: Record) -> Self {
+            let mut mem = vec![0xff; ((2 * SLOT_BLOCKS + 1) * BLOCK_SIZE) as usize];
+            for (i, b) in mem.iter_mut().enumerate() {
+                let block = i as u32 / BLOCK_SIZE;
+                *b = (block as u8).wrapping_mul(37) ^ (i as u8);
+            }
+            Self {
+                mem,
+                record,
+                ops_left: usize::MAX,
+            }
+        }
+
+        fn block(&self, addr: u32) -> &[u8] {
+            &self.mem[addr as usize..(addr + BLOCK_SIZE) as usize]
+        }
+
+        fn op(&mut self) -> Result<(), PowerLoss> {
+            if self.ops_left == 0 {
+                return Err(PowerLoss);
+            }
+            self.ops_left -= 1;
+            Ok(())
+        }
+    }
+
+    impl SwapFlash for MockFlash {
+        type Error = PowerLoss;
+
+        fn copy_block(&mut self, from: u32, to: u32) -> Result<(), PowerLoss> {
+            let (from, to, len) = (from as usize, to as usize, BLOCK_SIZE as usize);
+            let data = self.mem[from..from + len].to_vec();
+            self.mem[to..to + len].fill(0xff);
+            if self.op().is_err() {
+                self.mem[to..to + len / 2].copy_from_slice(&data[..len / 2]);
+                return Err(PowerLoss);
+            }
+            self.mem[to..to + len].copy_from_slice(&data);
+            Ok(())
+        }
+
+        fn block_crc(&mut self, addr: u32) -> Result<u32, PowerLoss> {
+            Ok(crc32(self.block(addr)))
+        }
+
+        fn store(&mut self, record: Record) -> Result<(), PowerLoss> {
+            self.op()?;
+            self.record = record;
+            Ok(())
+        }
+    }
+
+    /// Swap from the last stored record
+    fn resume(flash: &mut MockFlash) -> Result<(), PowerLoss> {
+        let record = flash.record;
+        swap(flash, SLOTS, record)
+    }
+
+    fn swap_record(len: u32) -> Record {
+        Record {
+            state: State::Swap,
+            len,
+            ..Record::EMPTY
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let record = Record {
+            seq: 7,
+            state: State::Revert,
+            progress: 3,
+            len: 0x1_2345,
+            active_crc: 0xdead_beef,
+            dfu_crc: 0x0bad_f00d,
+            header: ImageHeader::new(b"image"),
+        };
+        let mut bytes = record.to_bytes();
+        assert_eq!(Record::from_bytes(&bytes), Some(record));
+
+        bytes[13] ^= 1;
+        assert_eq!(Record::from_bytes(&bytes), None);
+        assert_eq!(Record::from_bytes(&[0xff; RECORD_SIZE]), None);
+    }
+
+    #[test]
+    fn swap_bounded_by_len() {
+        let mut flash = MockFlash::new(swap_record(BLOCK_SIZE + 1));
+        let before = flash.mem.clone();
+        resume(&mut flash).unwrap();
+
+        let slot = (SLOT_BLOCKS * BLOCK_SIZE) as usize;
+        let len = 2 * BLOCK_SIZE as usize;
+        assert_eq!(flash.mem[..len], before[slot..slot + len]);
+        assert_eq!(flash.mem[slot..slot + len], before[..len]);
+        // blocks past `len` are not touched
+        assert_eq!(flash.mem[len..slot], before[len..slot]);
+        assert_eq!(flash.mem[slot + len..2 * slot], before[slot + len..2 * slot]);
+        assert_eq!(flash.record.progress, 2);
+    }
+
+    #[test]
+    fn swap_resumes_after_power_loss() {
+        let len = SLOT_BLOCKS * BLOCK_SIZE;
+        let expected = {
+            let mut flash = MockFlash::new(swap_record(len));
+            resume(&mut flash).unwrap();
+            flash.mem
+        };
+
+        // fail at every copy and store, once, then resume from the stored record
+        for fail_at in 0..(SLOT_BLOCKS * 4) as usize {
+            let mut flash = MockFlash::new(swap_record(len));
+            flash.ops_left = fail_at;
+            assert!(resume(&mut flash).is_err());
+
+            flash.ops_left = usize::MAX;
+            resume(&mut flash).unwrap();
+            let slots = ..(2 * len) as usize;
+            assert_eq!(flash.mem[slots], expected[slots], "power loss at op {}", fail_at);
+        }
+    }
+
+    #[test]
+    fn swap_resumes_after_repeated_power_loss() {
+        let len = SLOT_BLOCKS * BLOCK_SIZE;
+        let expected = {
+            let mut flash = MockFlash::new(swap_record(len));
+            resume(&mut flash).unwrap();
+            flash.mem
+        };
+
+        // lose power on the first operation of every resume, until the swap gets through
+        let mut flash = MockFlash::new(swap_record(len));
+        let mut resumes = 0;
+        flash.ops_left = 0;
+        while resume(&mut flash).is_err() {
+            resumes += 1;
+            assert!(resumes < 100);
+            flash.ops_left = resumes % 3;
+        }
+        let slots = ..(2 * len) as usize;
+        assert_eq!(flash.mem[slots], expected[slots]);
+    }
+}
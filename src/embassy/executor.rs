@@ -0,0 +1,224 @@
+//! Embassy executors for the QingKe core.
+//!
+//! - [`Executor`]: thread mode executor, sleeps with WFI (or WFE) when no task is ready
+//! - [`InterruptExecutor`]: runs tasks in an interrupt handler, so tasks can preempt the thread mode ones
+//!
+//! There's no `#[embassy_executor::main]` for this target, start the executor from the entry point.
+//! [`Executor::run`] needs a `&'static mut`, keep it in a static:
+//!
+//! ```ignore
+//! static mut EXECUTOR: Option<Executor> = None;
+//!
+//! #[ch32v_rt::entry]
+//! fn main() -> ! {
+//!     let p = hal::init(Default::default());
+//!
+//!     let executor = unsafe {
+//!         EXECUTOR = Some(Executor::new());
+//!         EXECUTOR.as_mut().unwrap()
+//!     };
+//!     executor.run(|spawner| {
+//!         spawner.spawn(blink(p.PB4.degrade())).unwrap();
+//!     })
+//! }
+//! ```
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_executor::raw::{self, Pender};
+use embassy_executor::{SendSpawner, Spawner};
+
+use crate::interrupt::{InterruptExt, Priority};
+use crate::pac;
+use crate::rt::Interrupt;
+
+/// PFIC_SCTLR
+const SCTLR_SLEEPDEEP: u32 = 1 << 2;
+const SCTLR_WFITOWFE: u32 = 1 << 3;
+
+static SIGNAL_WORK_THREAD_MODE: AtomicBool = AtomicBool::new(false);
+
+/// How the core waits when no task is ready
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdleMode {
+    /// Wait for interrupt
+    #[default]
+    Wfi,
+    /// Wait for event, WFI is executed as WFE. Pending interrupts wake the core even when disabled.
+    Wfe,
+}
+
+/// Thread mode executor, using WFI/WFE.
+///
+/// This executor runs tasks in thread mode. When there's no more work to do, it sleeps until an
+/// interrupt wakes a task. The core stays in the light sleep mode, peripherals keep running.
+pub struct Executor {
+    inner: raw::Executor,
+    idle: IdleMode,
+    not_send: PhantomData<*mut ()>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self::with_idle_mode(IdleMode::Wfi)
+    }
+
+    pub fn with_idle_mode(idle: IdleMode) -> Self {
+        Self {
+            inner: raw::Executor::new(Pender::new_from_callback(pend_thread, ptr::null_mut())),
+            idle,
+            not_send: PhantomData,
+        }
+    }
+
+    /// Run the executor.
+    ///
+    /// The `init` closure is called with a [`Spawner`] that spawns tasks on
+    /// this executor. Use it to spawn the initial task(s). After `init` returns,
+    /// the executor starts running the tasks.
+    ///
+    /// This function requires `&'static mut self`. This means you have to store the
+    /// Executor instance in a place where it'll live forever and grants you mutable
+    /// access. There's a few ways to do this:
+    ///
+    /// - a [StaticCell](https://docs.rs/static_cell/latest/static_cell/) (safe)
+    /// - a `static mut` (unsafe)
+    /// - a local variable in a function you know never returns (like `fn main() -> !`), upgrading its lifetime with `transmute`. (unsafe)
+    ///
+    /// This function never returns.
+    pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
+        init(self.inner.spawner());
+
+        let pfic = unsafe { &*pac::PFIC::PTR };
+        pfic.sctlr.modify(|r, w| unsafe {
+            let bits = r.bits() & !SCTLR_SLEEPDEEP;
+            match self.idle {
+                IdleMode::Wfi => w.bits(bits & !SCTLR_WFITOWFE),
+                IdleMode::Wfe => w.bits(bits | SCTLR_WFITOWFE),
+            }
+        });
+
+        loop {
+            unsafe {
+                self.inner.poll();
+                // An interrupt between the check and `wfi` keeps pending, and wakes the core at once.
+                critical_section::with(|_| {
+                    if SIGNAL_WORK_THREAD_MODE.load(Ordering::SeqCst) {
+                        SIGNAL_WORK_THREAD_MODE.store(false, Ordering::SeqCst);
+                    } else {
+                        riscv::asm::wfi();
+                    }
+                });
+            }
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn pend_thread(_context: *mut ()) {
+    SIGNAL_WORK_THREAD_MODE.store(true, Ordering::SeqCst);
+}
+
+/// Interrupt mode executor.
+///
+/// This executor runs tasks in interrupt mode. The interrupt handler is set up
+/// to poll tasks, and when a task is woken the interrupt is pended from software.
+///
+/// Any interrupt not used by a peripheral works, `Software` is the natural choice.
+/// You must call [`InterruptExecutor::on_interrupt`] from its handler.
+///
+/// Tasks in a higher priority executor preempt tasks in lower priority ones, and thread mode.
+pub struct InterruptExecutor {
+    started: AtomicBool,
+    executor: UnsafeCell<MaybeUninit<raw::Executor>>,
+}
+
+unsafe impl Send for InterruptExecutor {}
+unsafe impl Sync for InterruptExecutor {}
+
+impl InterruptExecutor {
+    pub const fn new() -> Self {
+        Self {
+            started: AtomicBool::new(false),
+            executor: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Executor interrupt callback.
+    ///
+    /// # Safety
+    ///
+    /// You MUST call this from the interrupt handler, and from nowhere else.
+    pub unsafe fn on_interrupt(&'static self) {
+        if !self.started.load(Ordering::Acquire) {
+            return;
+        }
+        let executor = unsafe { (*self.executor.get()).assume_init_ref() };
+        executor.poll();
+    }
+
+    /// Start the executor, on the given interrupt and priority.
+    ///
+    /// Returns a [`SendSpawner`] to spawn tasks from any context.
+    pub fn start(&'static self, irq: Interrupt, priority: Priority) -> SendSpawner {
+        if self
+            .started
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            panic!("InterruptExecutor::start() called multiple times on the same executor.");
+        }
+
+        unsafe {
+            (*self.executor.get())
+                .as_mut_ptr()
+                .write(raw::Executor::new(Pender::new_from_callback(
+                    pend_interrupt,
+                    irq as u16 as usize as *mut (),
+                )))
+        }
+
+        let executor = unsafe { (*self.executor.get()).assume_init_ref() };
+
+        irq.set_priority(priority);
+        unsafe { irq.enable() };
+
+        executor.spawner().make_send()
+    }
+
+    /// Get a SendSpawner for this executor
+    ///
+    /// This returns a [`SendSpawner`] you can use to spawn tasks on this
+    /// executor.
+    ///
+    /// This MUST only be called on an executor that has already been started.
+    /// The function will panic otherwise.
+    pub fn spawner(&'static self) -> SendSpawner {
+        if !self.started.load(Ordering::Acquire) {
+            panic!("InterruptExecutor::spawner() called on uninitialized executor.");
+        }
+        let executor = unsafe { (*self.executor.get()).assume_init_ref() };
+        executor.spawner().make_send()
+    }
+}
+
+impl Default for InterruptExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn pend_interrupt(context: *mut ()) {
+    // safety: the number comes from an `Interrupt` in `InterruptExecutor::start`
+    let irq: Interrupt = unsafe { core::mem::transmute(context as usize as u16) };
+    irq.pend();
+}
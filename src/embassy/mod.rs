@@ -1,10 +1,16 @@
-use core::cell::Cell;
+//! Embassy integration
+//!
+//! - `time_driver`: embassy-time driver on SysTick, behind the `time-driver-systick` feature
+//! - `executor`: thread mode and interrupt mode executors
 
-use self::time_driver::EmbassyTimer;
+use core::cell::Cell;
 
-mod time_driver;
+pub mod executor;
+#[cfg(feature = "time-driver-systick")]
+pub mod time_driver;
 
 pub struct AlarmState {
+    /// `u64::MAX` when no alarm is set
     pub timestamp: Cell<u64>,
     pub callback: Cell<Option<(fn(*mut ()), *mut ())>>,
     pub allocated: Cell<bool>,
@@ -15,9 +21,15 @@ unsafe impl Send for AlarmState {}
 impl AlarmState {
     pub const fn new() -> Self {
         Self {
-            timestamp: Cell::new(0),
+            timestamp: Cell::new(u64::MAX),
             callback: Cell::new(None),
             allocated: Cell::new(false),
         }
     }
 }
+
+/// Called by [`crate::init`], after clocks are configured
+pub(crate) fn init() {
+    #[cfg(feature = "time-driver-systick")]
+    time_driver::init();
+}
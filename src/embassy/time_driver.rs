@@ -1,9 +1,169 @@
+//! Embassy time driver on SysTick.
+//!
+//! The 64-bit SysTick counter runs at HCLK/8 and never overflows, its compare match is shared by all
+//! alarms: it is always programmed to the earliest pending one.
+//!
+//! Enabled by the `time-driver-systick` feature, which also defines the global `SysTick` interrupt
+//! handler. Leave the feature off to provide your own embassy-time driver or SysTick handler.
+//! The driver owns SysTick, it is left out of [`crate::Peripherals`] when the feature is enabled.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
 use critical_section::{CriticalSection, Mutex};
+use embassy_time::driver::{AlarmHandle, Driver};
+use embassy_time::TICK_HZ;
 
 use super::AlarmState;
+use crate::interrupt::Interrupt;
+use crate::systick;
 
-pub const ALARM_COUNT: usize = 1;
+pub const ALARM_COUNT: usize = 4;
 
 pub struct EmbassyTimer {
     pub(crate) alarms: Mutex<[AlarmState; ALARM_COUNT]>,
+    /// SysTick counter frequency, HCLK/8
+    pub(crate) freq: AtomicU32,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ALARM_STATE_NEW: AlarmState = AlarmState::new();
+
+embassy_time::time_driver_impl!(static DRIVER: EmbassyTimer = EmbassyTimer {
+    alarms: Mutex::new([ALARM_STATE_NEW; ALARM_COUNT]),
+    freq: AtomicU32::new(0),
+});
+
+impl EmbassyTimer {
+    fn init(&'static self) {
+        self.freq
+            .store(crate::sysctl::clocks().hclk.to_Hz() / 8, Ordering::Relaxed);
+
+        systick::write_compare(u64::MAX);
+        systick::clear_flag();
+        systick::start_counter(true);
+
+        unsafe { crate::interrupt::SysTick::enable() };
+    }
+
+    /// SysTick ticks to embassy ticks, time stands at zero until [`crate::init`]
+    fn to_embassy_ticks(&self, ticks: u64) -> u64 {
+        let freq = self.freq.load(Ordering::Relaxed) as u64;
+        if freq == 0 {
+            return 0;
+        }
+        (ticks / freq) * TICK_HZ + (ticks % freq) * TICK_HZ / freq
+    }
+
+    /// Embassy ticks to SysTick ticks, rounded up so alarms never fire early
+    fn to_systick_ticks(&self, ticks: u64) -> u64 {
+        let freq = self.freq.load(Ordering::Relaxed) as u64;
+        (ticks / TICK_HZ) * freq + ((ticks % TICK_HZ) * freq + TICK_HZ - 1) / TICK_HZ
+    }
+
+    fn get_alarm<'a>(&'a self, cs: CriticalSection<'a>, alarm: AlarmHandle) -> &'a AlarmState {
+        // safety: we're allowed to assume the AlarmState is created by us, and
+        // we never create one that's out of bounds.
+        unsafe { self.alarms.borrow(cs).get_unchecked(alarm.id() as usize) }
+    }
+
+    /// Program the compare register with the earliest pending alarm
+    fn reschedule(&self, cs: CriticalSection) {
+        let next = self
+            .alarms
+            .borrow(cs)
+            .iter()
+            .map(|a| a.timestamp.get())
+            .min()
+            .unwrap_or(u64::MAX);
+
+        if next == u64::MAX {
+            systick::write_compare(u64::MAX);
+            return;
+        }
+
+        let target = self.to_systick_ticks(next);
+        let now = systick::read_counter();
+        // an alarm in the past, fire as soon as possible
+        systick::write_compare(target.max(now + 1));
+    }
+
+    fn on_interrupt(&self) {
+        systick::clear_flag();
+
+        critical_section::with(|cs| {
+            let now = self.now();
+            for alarm in self.alarms.borrow(cs) {
+                if alarm.timestamp.get() <= now {
+                    alarm.timestamp.set(u64::MAX);
+                    if let Some((f, ctx)) = alarm.callback.get() {
+                        f(ctx);
+                    }
+                }
+            }
+            self.reschedule(cs);
+        });
+    }
+}
+
+impl Driver for EmbassyTimer {
+    fn now(&self) -> u64 {
+        self.to_embassy_ticks(systick::read_counter())
+    }
+
+    unsafe fn allocate_alarm(&self) -> Option<AlarmHandle> {
+        critical_section::with(|cs| {
+            let alarms = self.alarms.borrow(cs);
+            let id = alarms.iter().position(|a| !a.allocated.get())?;
+            alarms[id].allocated.set(true);
+            Some(AlarmHandle::new(id as u8))
+        })
+    }
+
+    fn set_alarm_callback(&self, alarm: AlarmHandle, callback: fn(*mut ()), ctx: *mut ()) {
+        critical_section::with(|cs| {
+            let alarm = self.get_alarm(cs, alarm);
+            alarm.callback.set(Some((callback, ctx)));
+        })
+    }
+
+    fn set_alarm(&self, alarm: AlarmHandle, timestamp: u64) -> bool {
+        critical_section::with(|cs| {
+            let alarm = self.get_alarm(cs, alarm);
+
+            if timestamp <= self.now() {
+                // already passed, the caller handles it, the alarm must not fire
+                alarm.timestamp.set(u64::MAX);
+                self.reschedule(cs);
+                return false;
+            }
+
+            alarm.timestamp.set(timestamp);
+            self.reschedule(cs);
+            true
+        })
+    }
+}
+
+#[cfg(target_arch = "riscv32")]
+core::arch::global_asm!(
+    r#"
+    .section .trap, "ax"
+    .global SysTick
+    SysTick:
+    addi sp, sp, -4
+    sw ra, 0(sp)
+    jal _embassy_time_SysTick
+    lw ra, 0(sp)
+    addi sp, sp, 4
+    mret
+"#
+);
+
+#[no_mangle]
+extern "C" fn _embassy_time_SysTick() {
+    DRIVER.on_interrupt();
+}
+
+pub(crate) fn init() {
+    DRIVER.init()
 }
@@ -0,0 +1,352 @@
+//! On-chip flash
+//!
+//! Data-Flash (EEPROM) is 32KB, byte readable and writable, and erased in 256 byte blocks.
+//! Code Flash is 448KB, mapped at 0, written in dwords and erased in 4KB blocks.
+//! Writes go through the ISP ROM library, which needs 4-byte aligned buffers.
+//!
+//! [`set_lock_level`] write-protects the flash until the next reset. Readout protection is part of
+//! the option bytes, set by the programmer, and can only be queried here.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+use crate::isp::{
+    self, EEPROM_BLOCK_SIZE, EEPROM_MAX_SIZE, EEPROM_MIN_ERASE_SIZE, EEPROM_MIN_WRITE_SIZE, EEPROM_PAGE_SIZE,
+    FLASH_ROM_MAX_SIZE, FLASH_ROM_MIN_WRITE_SIZE,
+};
+use crate::peripherals::{EEPROM, FLASH};
+use crate::{into_ref, pac, Peripheral, PeripheralRef};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Offset or length is not a multiple of the erase or write size
+    NotAligned,
+    /// Range is outside of the flash
+    OutOfBounds,
+    /// The ROM library returned a failure status
+    Failed,
+    /// Range overlaps the running image
+    Protected,
+    /// Flash content differs from the data
+    Verify,
+    /// Write protected by [`set_lock_level`], or a Code Flash write or erase failed under
+    /// [`LockLevel::Boot`]
+    Locked,
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::NotAligned => NorFlashErrorKind::NotAligned,
+            Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Error::Failed | Error::Protected | Error::Verify | Error::Locked => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+// R8_GLOB_CFG_INFO
+const RB_CFG_ROM_READ: u8 = 0x01;
+
+static LOCK_LEVEL: AtomicU8 = AtomicU8::new(LockLevel::Unlocked as u8);
+
+/// Flash write protection, cleared by any reset
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LockLevel {
+    Unlocked = 0,
+    /// Boot code is write protected
+    Boot = 1,
+    /// All of Code Flash and Data-Flash is write protected
+    All = 3,
+}
+
+/// Set the write protection level, returns the level now in effect. It holds until the next reset.
+pub fn set_lock_level(level: LockLevel) -> Result<LockLevel, Error> {
+    if isp::flash_rom_lock(level as u8) != 0 {
+        return Err(Error::Failed);
+    }
+    LOCK_LEVEL.store(level as u8, Ordering::Relaxed);
+    Ok(level)
+}
+
+/// Write protection level set by [`set_lock_level`] since reset.
+///
+/// This is a software shadow, the ROM lock can't be read back. A lock set by other code, e.g. a
+/// bootloader, is not reflected.
+pub fn lock_level() -> LockLevel {
+    match LOCK_LEVEL.load(Ordering::Relaxed) {
+        0 => LockLevel::Unlocked,
+        1 => LockLevel::Boot,
+        _ => LockLevel::All,
+    }
+}
+
+/// Whether an external programmer is denied reading Code and Data Flash
+pub fn is_readout_protected() -> bool {
+    let sys = unsafe { &*pac::SYS::PTR };
+    sys.glob_cfg_info.read().bits() & RB_CFG_ROM_READ == 0
+}
+
+fn check_unlocked() -> Result<(), Error> {
+    if lock_level() == LockLevel::All {
+        return Err(Error::Locked);
+    }
+    Ok(())
+}
+
+/// Code Flash ROM call failure, the range may overlap the boot code
+fn rom_failed() -> Error {
+    if lock_level() == LockLevel::Boot {
+        Error::Locked
+    } else {
+        Error::Failed
+    }
+}
+
+/// ROM calls need 4-byte aligned buffers
+#[repr(C, align(4))]
+struct Bounce([u8; EEPROM_PAGE_SIZE as usize]);
+
+fn check_range(offset: u32, len: usize, capacity: u32, align: u32) -> Result<(), Error> {
+    let end = offset.checked_add(len as u32).ok_or(Error::OutOfBounds)?;
+    if end > capacity {
+        return Err(Error::OutOfBounds);
+    }
+    if offset % align != 0 || len as u32 % align != 0 {
+        return Err(Error::NotAligned);
+    }
+    Ok(())
+}
+
+/// Data-Flash, addressed from 0
+pub struct DataFlash<'d> {
+    _inner: PeripheralRef<'d, EEPROM>,
+}
+
+impl<'d> DataFlash<'d> {
+    pub fn new(p: impl Peripheral<P = EEPROM> + 'd) -> Self {
+        into_ref!(p);
+
+        Self { _inner: p }
+    }
+
+    pub fn blocking_read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        check_range(offset, bytes.len(), EEPROM_MAX_SIZE, 1)?;
+
+        let mut buf = Bounce([0; EEPROM_PAGE_SIZE as usize]);
+        let mut addr = offset;
+        for chunk in bytes.chunks_mut(EEPROM_PAGE_SIZE as usize) {
+            let tmp = &mut buf.0[..chunk.len()];
+            if isp::eeprom_read(addr, tmp) != 0 {
+                return Err(Error::Failed);
+            }
+            chunk.copy_from_slice(tmp);
+            addr += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    /// Write `bytes` to erased flash
+    pub fn blocking_write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        check_unlocked()?;
+        check_range(offset, bytes.len(), EEPROM_MAX_SIZE, EEPROM_MIN_WRITE_SIZE)?;
+
+        let mut buf = Bounce([0; EEPROM_PAGE_SIZE as usize]);
+        let mut addr = offset;
+        for chunk in bytes.chunks(EEPROM_PAGE_SIZE as usize) {
+            let tmp = &mut buf.0[..chunk.len()];
+            tmp.copy_from_slice(chunk);
+            if isp::eeprom_write(addr, tmp) != 0 {
+                return Err(Error::Failed);
+            }
+            addr += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    /// Erase `from..to`, both aligned to 256 bytes
+    pub fn blocking_erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        check_unlocked()?;
+        if to < from {
+            return Err(Error::OutOfBounds);
+        }
+        check_range(from, (to - from) as usize, EEPROM_MAX_SIZE, EEPROM_MIN_ERASE_SIZE)?;
+
+        if from != to && isp::eeprom_erase(from, to - from) != 0 {
+            return Err(Error::Failed);
+        }
+        Ok(())
+    }
+}
+
+impl<'d> ErrorType for DataFlash<'d> {
+    type Error = Error;
+}
+
+impl<'d> ReadNorFlash for DataFlash<'d> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.blocking_read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        EEPROM_MAX_SIZE as usize
+    }
+}
+
+impl<'d> NorFlash for DataFlash<'d> {
+    const WRITE_SIZE: usize = EEPROM_MIN_WRITE_SIZE as usize;
+    const ERASE_SIZE: usize = EEPROM_MIN_ERASE_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.blocking_erase(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.blocking_write(offset, bytes)
+    }
+}
+
+extern "C" {
+    static _data_lma: u8;
+    static _data_vma: u8;
+    static _edata: u8;
+}
+
+/// End of the running image in Code Flash, `.text` and `.rodata` followed by the `.data` load image
+pub fn image_end() -> u32 {
+    unsafe {
+        let data_lma = &_data_lma as *const u8 as u32;
+        let data_len = &_edata as *const u8 as u32 - &_data_vma as *const u8 as u32;
+        data_lma + data_len
+    }
+}
+
+/// Code Flash is mapped at 0, a null pointer to Rust
+#[inline]
+fn read_word(addr: u32) -> u32 {
+    #[cfg(target_arch = "riscv32")]
+    {
+        let word: u32;
+        unsafe {
+            core::arch::asm!("lw {0}, 0({1})", out(reg) word, in(reg) addr, options(readonly, nostack));
+        }
+        word
+    }
+    #[cfg(not(target_arch = "riscv32"))]
+    unimplemented!("read {:#x}", addr)
+}
+
+/// Code Flash, addressed from 0. Erase and write refuse to touch the running image.
+pub struct CodeFlash<'d> {
+    _inner: PeripheralRef<'d, FLASH>,
+}
+
+impl<'d> CodeFlash<'d> {
+    pub fn new(p: impl Peripheral<P = FLASH> + 'd) -> Self {
+        into_ref!(p);
+
+        Self { _inner: p }
+    }
+
+    fn check_writable(from: u32, to: u32) -> Result<(), Error> {
+        // the image starts at 0
+        if from < to && from < image_end() {
+            return Err(Error::Protected);
+        }
+        Ok(())
+    }
+
+    pub fn blocking_read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        check_range(offset, bytes.len(), FLASH_ROM_MAX_SIZE, 1)?;
+
+        for (i, b) in bytes.iter_mut().enumerate() {
+            let addr = offset + i as u32;
+            *b = (read_word(addr & !0x03) >> ((addr & 0x03) * 8)) as u8;
+        }
+        Ok(())
+    }
+
+    /// Write `bytes` to erased flash, dword aligned
+    pub fn blocking_write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        check_unlocked()?;
+        check_range(offset, bytes.len(), FLASH_ROM_MAX_SIZE, FLASH_ROM_MIN_WRITE_SIZE)?;
+        Self::check_writable(offset, offset + bytes.len() as u32)?;
+
+        let mut buf = Bounce([0; EEPROM_PAGE_SIZE as usize]);
+        let mut addr = offset;
+        for chunk in bytes.chunks(EEPROM_PAGE_SIZE as usize) {
+            let tmp = &mut buf.0[..chunk.len()];
+            tmp.copy_from_slice(chunk);
+            if isp::flash_rom_write(addr, tmp) != 0 {
+                return Err(rom_failed());
+            }
+            addr += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    /// Compare flash at `offset` with `bytes`, dword aligned
+    pub fn blocking_verify(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        check_range(offset, bytes.len(), FLASH_ROM_MAX_SIZE, FLASH_ROM_MIN_WRITE_SIZE)?;
+
+        let mut buf = Bounce([0; EEPROM_PAGE_SIZE as usize]);
+        let mut addr = offset;
+        for chunk in bytes.chunks(EEPROM_PAGE_SIZE as usize) {
+            let tmp = &mut buf.0[..chunk.len()];
+            tmp.copy_from_slice(chunk);
+            if isp::flash_rom_verify(addr, tmp) != 0 {
+                return Err(Error::Verify);
+            }
+            addr += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    /// Erase `from..to`, both aligned to 4KB blocks
+    pub fn blocking_erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        check_unlocked()?;
+        if to < from {
+            return Err(Error::OutOfBounds);
+        }
+        check_range(from, (to - from) as usize, FLASH_ROM_MAX_SIZE, EEPROM_BLOCK_SIZE)?;
+        Self::check_writable(from, to)?;
+
+        if from != to && isp::flash_rom_erase(from, to - from) != 0 {
+            return Err(rom_failed());
+        }
+        Ok(())
+    }
+}
+
+impl<'d> ErrorType for CodeFlash<'d> {
+    type Error = Error;
+}
+
+impl<'d> ReadNorFlash for CodeFlash<'d> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.blocking_read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_ROM_MAX_SIZE as usize
+    }
+}
+
+impl<'d> NorFlash for CodeFlash<'d> {
+    const WRITE_SIZE: usize = FLASH_ROM_MIN_WRITE_SIZE as usize;
+    const ERASE_SIZE: usize = EEPROM_BLOCK_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.blocking_erase(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.blocking_write(offset, bytes)
+    }
+}
@@ -726,15 +726,23 @@ macro_rules! foreach_pin {
             $(($pat) => $code;)*
             ($_:tt) => {}
         }
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
         __foreach_pin_inner!((PA0,GPIOA,0,0));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
         __foreach_pin_inner!((PA1,GPIOA,0,1));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
         __foreach_pin_inner!((PA2,GPIOA,0,2));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
         __foreach_pin_inner!((PA3,GPIOA,0,3));
         __foreach_pin_inner!((PA4,GPIOA,0,4));
         __foreach_pin_inner!((PA5,GPIOA,0,5));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
         __foreach_pin_inner!((PA6,GPIOA,0,6));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
         __foreach_pin_inner!((PA7,GPIOA,0,7));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
         __foreach_pin_inner!((PA8,GPIOA,0,8));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
         __foreach_pin_inner!((PA9,GPIOA,0,9));
         __foreach_pin_inner!((PA10,GPIOA,0,10));
         __foreach_pin_inner!((PA11,GPIOA,0,11));
@@ -743,15 +751,32 @@ macro_rules! foreach_pin {
         __foreach_pin_inner!((PA14,GPIOA,0,14));
         __foreach_pin_inner!((PA15,GPIOA,0,15));
         __foreach_pin_inner!((PB0,GPIOB,1,0));
+        __foreach_pin_inner!((PB1,GPIOB,1,1));
+        __foreach_pin_inner!((PB2,GPIOB,1,2));
+        __foreach_pin_inner!((PB3,GPIOB,1,3));
         __foreach_pin_inner!((PB4,GPIOB,1,4));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
+        __foreach_pin_inner!((PB5,GPIOB,1,5));
         __foreach_pin_inner!((PB6,GPIOB,1,6));
         __foreach_pin_inner!((PB7,GPIOB,1,7));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
+        __foreach_pin_inner!((PB8,GPIOB,1,8));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
+        __foreach_pin_inner!((PB9,GPIOB,1,9));
         __foreach_pin_inner!((PB10,GPIOB,1,10));
         __foreach_pin_inner!((PB11,GPIOB,1,11));
         __foreach_pin_inner!((PB12,GPIOB,1,12));
         __foreach_pin_inner!((PB13,GPIOB,1,13));
         __foreach_pin_inner!((PB14,GPIOB,1,14));
         __foreach_pin_inner!((PB15,GPIOB,1,15));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
+        __foreach_pin_inner!((PB16,GPIOB,1,16));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
+        __foreach_pin_inner!((PB17,GPIOB,1,17));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
+        __foreach_pin_inner!((PB18,GPIOB,1,18));
+        #[cfg(not(any(feature = "ch582", feature = "ch581")))]
+        __foreach_pin_inner!((PB19,GPIOB,1,19));
         __foreach_pin_inner!((PB20,GPIOB,1,20));
         __foreach_pin_inner!((PB21,GPIOB,1,21));
         __foreach_pin_inner!((PB22,GPIOB,1,22));
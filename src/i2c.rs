@@ -34,7 +34,7 @@ pub(crate) mod sealed {
     }
 }
 
-pub trait Instance: sealed::Instance + 'static {
+pub trait Instance: sealed::Instance + crate::rcc::RccPeripheral {
     type Interrupt: interrupt::Interrupt;
 }
 
@@ -111,7 +111,7 @@ impl<'d, T: Instance> I2c<'d, T> {
 
         let rb = T::regs();
 
-        // TODO: enable peripheral
+        <T as crate::rcc::sealed::RccPeripheral>::enable();
 
         // reset peripheral
         rb.ctrl1.modify(|_, w| w.swrst().set_bit());
@@ -394,6 +394,7 @@ impl<'d, T: Instance> I2c<'d, T> {
 impl<'d, T: Instance> Drop for I2c<'d, T> {
     fn drop(&mut self) {
         T::regs().ctrl1.modify(|_, w| w.pe().clear_bit());
+        <T as crate::rcc::sealed::RccPeripheral>::disable();
     }
 }
 
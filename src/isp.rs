@@ -114,12 +114,22 @@ pub fn eeprom_write(start_addr: u32, buf: &[u8]) -> u32 {
     unsafe { FLASH_EEPROM_CMD(RomCmd::EepromWrite as u8, start_addr, buf.as_ptr(), buf.len() as _) }
 }
 
-/*
-pub fn read_flash_rom(start_addr: u32, len: u32) -> &'static [u8] {
-    unsafe {
-        FLASH_EEPROM_CMD(RomCmd::FlashRomVerify
-             as u8, start_addr, TEST_BUF.as_mut_ptr(), len);
-        &TEST_BUF[..len as usize]
-    }
+/// `level`: 0 unlock all, 1 lock boot code, 3 lock all code and data. Called by [`crate::flash::set_lock_level`],
+/// which keeps track of the level.
+pub(crate) fn flash_rom_lock(level: u8) -> u32 {
+    unsafe { FLASH_EEPROM_CMD(RomCmd::FlashRomLock as u8, level as u32, ptr::null_mut(), 0) }
+}
+
+pub(crate) fn flash_rom_erase(start_addr: u32, len: u32) -> u32 {
+    unsafe { FLASH_EEPROM_CMD(RomCmd::FlashRomErase as u8, start_addr, ptr::null_mut(), len) }
+}
+
+/// `buf` must be 4-byte aligned, with a length multiple of 4
+pub(crate) fn flash_rom_write(start_addr: u32, buf: &[u8]) -> u32 {
+    unsafe { FLASH_EEPROM_CMD(RomCmd::FlashRomWrite as u8, start_addr, buf.as_ptr(), buf.len() as _) }
+}
+
+/// Compare flash with `buf`, 0 if equal. `buf` must be 4-byte aligned, with a length multiple of 4
+pub fn flash_rom_verify(start_addr: u32, buf: &[u8]) -> u32 {
+    unsafe { FLASH_EEPROM_CMD(RomCmd::FlashRomVerify as u8, start_addr, buf.as_ptr(), buf.len() as _) }
 }
-*/
@@ -1,16 +1,29 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![recursion_limit = "1024"]
 use core::ptr;
 
 pub use ch58x::ch58x as pac;
 
+#[cfg(any(
+    all(feature = "ch583", feature = "ch582"),
+    all(feature = "ch583", feature = "ch581"),
+    all(feature = "ch582", feature = "ch581"),
+))]
+compile_error!("select at most one chip feature: ch583, ch582 or ch581");
+
 pub use self::peripheral::{Peripheral, PeripheralRef};
 pub use self::peripherals::Peripherals;
 
 pub mod adc;
+pub mod battery;
+pub mod boot;
 pub mod dma;
+pub mod flash;
 pub mod gpio;
 pub mod i2c;
+pub mod power;
+pub mod pwm;
+pub mod rcc;
 // pub mod lcd;
 pub mod rtc;
 pub mod signature;
@@ -18,7 +31,9 @@ pub mod spi;
 pub mod sysctl;
 pub mod systick;
 pub mod timer;
+pub mod tkey;
 pub mod uart;
+pub mod wdt;
 
 // #[cfg(feature = "isp")]
 pub mod interrupt;
@@ -33,6 +48,7 @@ pub mod prelude;
 
 mod critical_section;
 
+#[cfg(feature = "embassy")]
 pub mod embassy;
 
 /// Bits per second
@@ -103,6 +119,7 @@ pub fn delay_us(t: u16) {
         _ => t << 1, // default 2us
     };
     i = i / 8;
+    #[cfg(target_arch = "riscv32")]
     unsafe {
         core::arch::asm!(
         "1:",
@@ -113,6 +130,9 @@ pub fn delay_us(t: u16) {
         options(nomem, nostack),
         );
     }
+    // host build, for unit tests
+    #[cfg(not(target_arch = "riscv32"))]
+    let _ = i;
 }
 
 pub fn delay_ms(t: u16) {
@@ -130,7 +150,25 @@ pub struct Config {
     pub enable_dcdc: bool,
 }
 
+/// Configure power and clocks, returns the peripheral singletons. The clock tree is available from
+/// [`sysctl::clocks`].
+///
+/// # Panics
+///
+/// If the clock config is invalid, use [`try_init`] to handle the error.
 pub fn init(config: Config) -> Peripherals {
+    match try_init(config) {
+        Ok((p, _)) => p,
+        Err(e) => panic!("invalid clock config: {:?}", e),
+    }
+}
+
+/// Configure power and clocks, returns the peripheral singletons and the frozen clock tree.
+///
+/// The clock config is validated before any register is touched.
+pub fn try_init(config: Config) -> Result<(Peripherals, sysctl::Clocks), sysctl::ClockError> {
+    config.clock.validate()?;
+
     let sys = unsafe { &*pac::SYS::PTR };
     if config.enable_dcdc {
         with_safe_access(|| {
@@ -149,7 +187,10 @@ pub fn init(config: Config) -> Peripherals {
         });
     }
 
-    config.clock.freeze();
+    let clocks = config.clock.freeze()?;
+
+    #[cfg(feature = "embassy")]
+    embassy::init();
 
     if config.low_power {
         unsafe {
@@ -161,7 +202,7 @@ pub fn init(config: Config) -> Peripherals {
             }
         }
     }
-    Peripherals::take()
+    Ok((Peripherals::take(), clocks))
 }
 
 pub unsafe fn reset() -> ! {
@@ -172,6 +213,84 @@ pub unsafe fn reset() -> ! {
     loop {}
 }
 
+/// Cause of the last reset, from R8_RESET_STATUS
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetReason {
+    /// Software reset, [`software_reset`] or [`reset`]
+    Software,
+    /// Power-on reset
+    PowerOn,
+    /// Watchdog timeout
+    Watchdog,
+    /// External RST pin
+    ManualPin,
+    /// Software reset while waking up from low power
+    WakeupSoftware,
+    /// Wake-up from shutdown
+    WakeupShutdown,
+    /// Watchdog timeout while waking up from low power
+    WakeupWatchdog,
+    /// External RST pin while waking up from low power
+    WakeupManualPin,
+}
+
+impl ResetReason {
+    pub fn read() -> Self {
+        let sys = unsafe { &*pac::SYS::PTR };
+
+        match sys.reset_status.read().bits() & 0x07 {
+            0 => Self::Software,
+            1 => Self::PowerOn,
+            2 => Self::Watchdog,
+            3 => Self::ManualPin,
+            4 => Self::WakeupSoftware,
+            5 => Self::WakeupShutdown,
+            6 => Self::WakeupWatchdog,
+            _ => Self::WakeupManualPin,
+        }
+    }
+
+    pub fn is_watchdog(&self) -> bool {
+        matches!(self, Self::Watchdog | Self::WakeupWatchdog)
+    }
+}
+
+/// Reset the chip via SYS, recorded as [`ResetReason::Software`]
+pub fn software_reset() -> ! {
+    const SOFTWARE_RESET: u8 = 0x01;
+    let sys = unsafe { &*pac::SYS::PTR };
+
+    with_safe_access(|| unsafe {
+        sys.rst_wdog_ctrl.modify(|r, w| w.bits(r.bits() | SOFTWARE_RESET));
+    });
+    loop {}
+}
+
+/// Soft reset of a single peripheral, e.g. `reset_peripheral(&mut p.SPI0)`.
+///
+/// There are no per-peripheral reset lines, this gates and ungates the clock, see
+/// [`rcc::RccPeripheral`]. Drivers re-initialize registers in their constructors.
+pub fn reset_peripheral<T: rcc::RccPeripheral>(_peri: impl Peripheral<P = T>) {
+    <T as rcc::sealed::RccPeripheral>::reset();
+}
+
+/// Jump to the on-chip ISP bootloader, for USB or UART firmware download.
+///
+/// # Safety
+///
+/// Peripherals are left as configured, the bootloader expects reset state, call this early.
+pub unsafe fn reset_to_bootloader() -> ! {
+    const BOOTLOADER_ADDR: usize = 0x0007_8000;
+
+    riscv::interrupt::disable();
+    qingke::register::gintenr::write(0);
+    #[cfg(target_arch = "riscv32")]
+    core::arch::asm!("jr {0}", in(reg) BOOTLOADER_ADDR, options(noreturn));
+    #[cfg(not(target_arch = "riscv32"))]
+    unimplemented!("jump to {:#x}", BOOTLOADER_ADDR)
+}
+
 // pin trait
 
 macro_rules! pin_trait_impl {
@@ -187,11 +306,43 @@ macro_rules! pin_trait_impl {
 pin_trait_impl!(crate::uart::TxPin, UART0, PB7, false);
 pin_trait_impl!(crate::uart::TxPin, UART0, PA14, true);
 
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
 pin_trait_impl!(crate::uart::TxPin, UART1, PA9, false);
 pin_trait_impl!(crate::uart::TxPin, UART1, PB13, true);
 
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
 pin_trait_impl!(crate::uart::TxPin, UART2, PA7, false);
 pin_trait_impl!(crate::uart::TxPin, UART2, PB23, true);
 
 pin_trait_impl!(crate::uart::TxPin, UART3, PA5, false);
 pin_trait_impl!(crate::uart::TxPin, UART3, PB21, true);
+
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+pin_trait_impl!(crate::timer::TimerPin, TMR0, PA9, false);
+pin_trait_impl!(crate::timer::TimerPin, TMR0, PB23, true);
+
+pin_trait_impl!(crate::timer::TimerPin, TMR1, PA10, false);
+pin_trait_impl!(crate::timer::TimerPin, TMR1, PB10, true);
+
+pin_trait_impl!(crate::timer::TimerPin, TMR2, PA11, false);
+pin_trait_impl!(crate::timer::TimerPin, TMR2, PB11, true);
+
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+pin_trait_impl!(crate::timer::TimerPin, TMR3, PA2, false);
+pin_trait_impl!(crate::timer::TimerPin, TMR3, PB22, true);
+
+pin_trait_impl!(crate::pwm::Pwm4Pin, PWMX, PA12, false);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+pin_trait_impl!(crate::pwm::Pwm4Pin, PWMX, PA6, true);
+pin_trait_impl!(crate::pwm::Pwm5Pin, PWMX, PA13, false);
+#[cfg(not(any(feature = "ch582", feature = "ch581")))]
+pin_trait_impl!(crate::pwm::Pwm5Pin, PWMX, PA7, true);
+pin_trait_impl!(crate::pwm::Pwm6Pin, PWMX, PB0, false);
+pin_trait_impl!(crate::pwm::Pwm7Pin, PWMX, PB4, false);
+pin_trait_impl!(crate::pwm::Pwm7Pin, PWMX, PB1, true);
+pin_trait_impl!(crate::pwm::Pwm8Pin, PWMX, PB6, false);
+pin_trait_impl!(crate::pwm::Pwm8Pin, PWMX, PB2, true);
+pin_trait_impl!(crate::pwm::Pwm9Pin, PWMX, PB7, false);
+pin_trait_impl!(crate::pwm::Pwm9Pin, PWMX, PB3, true);
+pin_trait_impl!(crate::pwm::Pwm10Pin, PWMX, PB14, false);
+pin_trait_impl!(crate::pwm::Pwm11Pin, PWMX, PB23, false);
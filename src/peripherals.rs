@@ -3,6 +3,8 @@ use crate::pac;
 // We need to export this in the hal for the drivers to use
 
 crate::peripherals! {
+    // owned by the embassy time driver
+    #[cfg(not(feature = "time-driver-systick"))]
     SYSTICK <= SYSTICK,
     UART0 <= UART0,
     UART1 <= UART1,
@@ -24,18 +26,29 @@ crate::peripherals! {
     TKEY <= virtual,
     BLE <= virtual,
     PWMX <= PWMX,
+    WDOG <= virtual,
+    BAT <= virtual,
+    EEPROM <= virtual,
+    FLASH <= virtual,
 
     // ADC_TEMP_SENSOR <= virtual,
     // ADC_VBAT_SENSOR <= virtual,
 
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PA7 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PA8 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PA9 <= virtual,
 
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PB9 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PB8 <= virtual,
 
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PB17 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PB16 <= virtual,
     PB15 <= virtual,
     PB14 <= virtual,
@@ -46,6 +59,7 @@ crate::peripherals! {
 
     PB7 <= virtual,
     PB6 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PB5 <= virtual,
     PB4 <= virtual,
     PB3 <= virtual,
@@ -57,16 +71,23 @@ crate::peripherals! {
     PB22 <= virtual,
     PB21 <= virtual,
     PB20 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PB19 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PB18 <= virtual,
 
     PA4 <= virtual,
     PA5 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PA6 <= virtual,
 
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PA0 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PA1 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PA2 <= virtual,
+    #[cfg(not(any(feature = "ch582", feature = "ch581")))]
     PA3 <= virtual,
 
     PA15 <= virtual,
@@ -0,0 +1,283 @@
+//! Low power modes
+//!
+//! - Idle: core clock stops, peripherals keep running, any interrupt wakes up
+//! - Halt: core and HSE/PLL stop, RAM and registers kept
+//! - Sleep: core powered down, selected RAM regions retained, 32K clock keeps running
+//! - Shutdown: only the wake-up logic and retained RAM are powered, wake up by reset
+//!
+//! Wake-up from halt, sleep and shutdown is configured by [`set_wakeup_sources`].
+//! BLE timing uses the RTC, so BLE connection events wake up through the RTC source.
+
+use crate::{pac, sysctl, with_safe_access};
+
+/// PFIC_SCTLR
+const SCTLR_SLEEPDEEP: u32 = 1 << 2;
+
+// R16_POWER_PLAN
+const PWR_RAM2K: u16 = 0x0002;
+const PWR_CORE: u16 = 0x0004;
+const PWR_EXTEND: u16 = 0x0008;
+const PWR_RAM30K: u16 = 0x0010;
+const PWR_DCDC_EN: u16 = 0x0200;
+const PWR_DCDC_PRE: u16 = 0x0400;
+const PWR_MUST_0010: u16 = 0x1000;
+const PWR_PLAN_EN: u16 = 0x8000;
+
+// R8_SLP_WAKE_CTRL
+const SLP_USB_WAKE: u8 = 0x01;
+const SLP_USB2_WAKE: u8 = 0x02;
+const SLP_RTC_WAKE: u8 = 0x08;
+const SLP_GPIO_WAKE: u8 = 0x10;
+const SLP_BAT_WAKE: u8 = 0x20;
+const WAKE_EV_MODE: u8 = 0x40;
+const SLP_GPIO_EDGE_MODE: u8 = 0x80;
+
+// R8_SLP_POWER_CTRL
+const RAM_RET_LV: u8 = 0x40;
+
+// R8_HFCK_PWR_CTRL
+const CLK_XT32M_PON: u8 = 0x04;
+
+/// HSE/5, 6.4MHz, while entering sleep
+const CLK_SYS_CFG_HSE_6_4MHZ: u16 = 0x05;
+
+/// Wake-up sources of halt, sleep and shutdown modes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WakeupSources {
+    pub usb: bool,
+    pub usb2: bool,
+    /// RTC timing and trigger events, also used by BLE
+    pub rtc: bool,
+    /// GPIO interrupts, the pins must have interrupt enabled
+    pub gpio: bool,
+    /// Battery voltage monitor
+    pub battery: bool,
+    /// GPIO wakes up on edge instead of level
+    pub gpio_edge: bool,
+}
+
+impl WakeupSources {
+    fn bits(&self) -> u8 {
+        let mut bits = 0;
+        if self.usb {
+            bits |= SLP_USB_WAKE;
+        }
+        if self.usb2 {
+            bits |= SLP_USB2_WAKE;
+        }
+        if self.rtc {
+            bits |= SLP_RTC_WAKE;
+        }
+        if self.gpio {
+            bits |= SLP_GPIO_WAKE;
+        }
+        if self.battery {
+            bits |= SLP_BAT_WAKE;
+        }
+        if self.gpio_edge {
+            bits |= SLP_GPIO_EDGE_MODE;
+        }
+        bits
+    }
+}
+
+/// Powered regions in sleep and shutdown modes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retention {
+    /// RAM 0x20000000..0x20007800
+    pub ram_30k: bool,
+    /// RAM 0x20007800..0x20008000
+    pub ram_2k: bool,
+    /// Extended registers, USB and BLE
+    pub extend: bool,
+}
+
+impl Default for Retention {
+    /// All RAM retained
+    fn default() -> Self {
+        Self {
+            ram_30k: true,
+            ram_2k: true,
+            extend: false,
+        }
+    }
+}
+
+impl Retention {
+    pub const NONE: Self = Self {
+        ram_30k: false,
+        ram_2k: false,
+        extend: false,
+    };
+
+    fn bits(&self) -> u16 {
+        let mut bits = 0;
+        if self.ram_30k {
+            bits |= PWR_RAM30K;
+        }
+        if self.ram_2k {
+            bits |= PWR_RAM2K;
+        }
+        if self.extend {
+            bits |= PWR_EXTEND;
+        }
+        bits
+    }
+}
+
+/// Set the wake-up sources, replacing the previous ones
+pub fn set_wakeup_sources(sources: WakeupSources) {
+    let sys = unsafe { &*pac::SYS::PTR };
+    with_safe_access(|| unsafe {
+        sys.slp_wake_ctrl.write(|w| w.bits(sources.bits() | WAKE_EV_MODE));
+    });
+}
+
+/// Idle mode, wait for interrupt.
+pub fn idle() {
+    set_deep_sleep(false);
+    unsafe {
+        riscv::asm::wfi();
+    }
+}
+
+/// Halt mode, HSE and PLL are stopped, execution continues after wake-up.
+pub fn halt() {
+    let sys = unsafe { &*pac::SYS::PTR };
+
+    let saved = prepare_oscillators();
+    with_safe_access(|| unsafe {
+        sys.pll_config.modify(|r, w| w.bits(r.bits() | (1 << 5)));
+    });
+
+    set_deep_sleep(true);
+    unsafe {
+        riscv::asm::wfi();
+        riscv::asm::nop();
+        riscv::asm::nop();
+    }
+    set_deep_sleep(false);
+
+    with_safe_access(|| unsafe {
+        sys.pll_config.modify(|r, w| w.bits(r.bits() & !(1 << 5)));
+    });
+    restore_oscillators(saved);
+}
+
+/// Sleep mode, core is powered down, execution continues after wake-up.
+///
+/// RAM holding the stack and data must be retained. HCLK is restored as configured by
+/// [`crate::init`].
+pub fn sleep(retention: Retention) {
+    let sys = unsafe { &*pac::SYS::PTR };
+
+    let saved = prepare_oscillators();
+    set_deep_sleep(true);
+
+    let power_plan = (sys.power_plan.read().bits() & (PWR_DCDC_EN | PWR_DCDC_PRE))
+        | PWR_PLAN_EN
+        | PWR_MUST_0010
+        | PWR_CORE
+        | retention.bits();
+
+    with_safe_access(|| unsafe {
+        sys.slp_power_ctrl.modify(|r, w| w.bits(r.bits() | RAM_RET_LV));
+        sys.power_plan.write(|w| w.bits(power_plan));
+        sys.hfck_pwr_ctrl.modify(|r, w| w.bits(r.bits() | CLK_XT32M_PON));
+        sys.clk_sys_cfg.write(|w| w.bits(CLK_SYS_CFG_HSE_6_4MHZ));
+    });
+
+    unsafe {
+        riscv::asm::wfi();
+        riscv::asm::nop();
+        riscv::asm::nop();
+    }
+    set_deep_sleep(false);
+
+    sysctl::refreeze();
+    restore_oscillators(saved);
+}
+
+/// Shutdown mode, lowest power. Wake-up resets the chip.
+pub fn shutdown(retention: Retention) -> ! {
+    let sys = unsafe { &*pac::SYS::PTR };
+
+    prepare_oscillators();
+    with_safe_access(|| unsafe {
+        sys.clk_sys_cfg.write(|w| w.bits(CLK_SYS_CFG_HSE_6_4MHZ));
+    });
+    set_deep_sleep(true);
+
+    with_safe_access(|| unsafe {
+        sys.slp_power_ctrl.modify(|r, w| w.bits(r.bits() | RAM_RET_LV));
+    });
+    with_safe_access(|| unsafe {
+        sys.power_plan
+            .write(|w| w.bits(PWR_PLAN_EN | PWR_MUST_0010 | retention.bits()));
+    });
+
+    unsafe {
+        riscv::asm::wfi();
+        riscv::asm::nop();
+        riscv::asm::nop();
+    }
+
+    // not reached, unless woken up while entering shutdown
+    set_deep_sleep(false);
+    unsafe { crate::reset() }
+}
+
+fn set_deep_sleep(deep: bool) {
+    let pfic = unsafe { &*pac::PFIC::PTR };
+    pfic.sctlr.modify(|r, w| unsafe {
+        if deep {
+            w.bits(r.bits() | SCTLR_SLEEPDEEP)
+        } else {
+            w.bits(r.bits() & !SCTLR_SLEEPDEEP)
+        }
+    });
+}
+
+/// Registers changed by [`prepare_oscillators`], put back by [`restore_oscillators`]
+struct Saved {
+    bat_det_ctrl: u8,
+    xt32m_tune: u8,
+}
+
+/// Faster HSE startup after wake-up, stronger LSE drive, battery monitor off unless it's a wake-up source.
+fn prepare_oscillators() -> Saved {
+    let sys = unsafe { &*pac::SYS::PTR };
+
+    let xt32m_tune = sys.xt32m_tune.read().bits();
+    let xt32m = (xt32m_tune & 0xfc) | 0x03; // 150% bias current
+    let mut xt32k = sys.xt32k_tune.read().bits();
+    if sys.ck32k_config.read().clk_osc32k_xt().bit_is_set() {
+        xt32k = (xt32k & 0xfc) | 0x01;
+    }
+    let bat_wake = sys.slp_wake_ctrl.read().bits() & SLP_BAT_WAKE != 0;
+    let bat_det_ctrl = sys.bat_det_ctrl.read().bits();
+
+    with_safe_access(|| unsafe {
+        if !bat_wake {
+            sys.bat_det_ctrl.write(|w| w.bits(0));
+        }
+        sys.xt32k_tune.write(|w| w.bits(xt32k));
+        sys.xt32m_tune.write(|w| w.bits(xt32m));
+    });
+
+    Saved {
+        bat_det_ctrl,
+        xt32m_tune,
+    }
+}
+
+/// Restore the HSE bias current of [`crate::sysctl::Config`] and the battery monitor as configured
+/// before entering low power, e.g. by [`crate::battery`]
+fn restore_oscillators(saved: Saved) {
+    let sys = unsafe { &*pac::SYS::PTR };
+
+    with_safe_access(|| unsafe {
+        sys.xt32m_tune.write(|w| w.bits(saved.xt32m_tune));
+        sys.bat_det_ctrl.write(|w| w.bits(saved.bat_det_ctrl));
+    });
+}
@@ -0,0 +1,537 @@
+//! PWMX, 8-channel PWM, PWM4 to PWM11.
+//!
+//! All channels share the same clock divider and cycle, each channel has its own duty and polarity.
+//! PWM frequency is `Fsys / clock_div / cycle`.
+//!
+//! | Channel | Pin  | Remap |
+//! |---------|------|-------|
+//! | PWM4    | PA12 | PA6   |
+//! | PWM5    | PA13 | PA7   |
+//! | PWM6    | PB0  | -     |
+//! | PWM7    | PB4  | PB1   |
+//! | PWM8    | PB6  | PB2   |
+//! | PWM9    | PB7  | PB3   |
+//! | PWM10   | PB14 | -     |
+//! | PWM11   | PB23 | -     |
+//!
+//! The remap bit is shared by PWM4, PWM5, PWM7, PWM8 and PWM9, they must use the same mapping.
+//! Channels borrow the [`Pwm`], each channel can be taken once at a time.
+
+use core::cell::Cell;
+
+use fugit::HertzU32 as Hertz;
+
+use crate::traits::pin_trait;
+use crate::{into_ref, pac, peripherals, Peripheral, PeripheralRef};
+
+// R16_PWM_CTRL, R8_PWM_OUT_EN in the low byte, R8_PWM_POLAR in the high byte
+const PWM_POLAR_SHIFT: u16 = 8;
+
+// R16_PWM_CYCLE, R8_PWM_CONFIG in the low byte, R8_PWM_CLOCK_DIV in the high byte
+const RB_PWM_CYCLE_MASK: u16 = 0x0d;
+const RB_PWM_STAG_EN_SHIFT: u16 = 4;
+const PWM_CLOCK_DIV_SHIFT: u16 = 8;
+
+// R8_PWM_INT_CTRL
+const RB_PWM_IE_CYC: u8 = 0x01;
+const RB_PWM_CYC_PRE: u8 = 0x02;
+const RB_PWM_IF_CYC: u8 = 0x80;
+
+/// Channels sharing the remap bit: PWM4, PWM5, PWM7, PWM8, PWM9
+const REMAPPABLE: u8 = 0b0011_1011;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The channel is already in use
+    ChannelInUse,
+    /// The pin mapping differs from the one used by the other remappable channels
+    RemapConflict,
+}
+
+/// PWM channel
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Channel {
+    Ch4 = 0,
+    Ch5 = 1,
+    Ch6 = 2,
+    Ch7 = 3,
+    Ch8 = 4,
+    Ch9 = 5,
+    Ch10 = 6,
+    Ch11 = 7,
+}
+
+impl Channel {
+    fn mask(self) -> u16 {
+        1 << (self as u16)
+    }
+
+    fn polar_mask(self) -> u16 {
+        self.mask() << PWM_POLAR_SHIFT
+    }
+}
+
+/// Channel pairs for staggered output, the second channel starts at the middle of the cycle.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChannelPair {
+    Ch4Ch5 = 0,
+    Ch6Ch7 = 1,
+    Ch8Ch9 = 2,
+    Ch10Ch11 = 3,
+}
+
+/// PWM cycle, in PWM clocks. Duty ranges from 0 to cycle.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Cycle {
+    /// 8-bit data width
+    #[default]
+    Cycle256,
+    Cycle255,
+    /// 7-bit data width
+    Cycle128,
+    Cycle127,
+    /// 6-bit data width
+    Cycle64,
+    Cycle63,
+    /// 5-bit data width
+    Cycle32,
+    Cycle31,
+}
+
+impl Cycle {
+    // RB_PWM_CYC_MOD[3:2], RB_PWM_CYCLE_SEL[0]
+    fn bits(self) -> u16 {
+        match self {
+            Cycle::Cycle256 => 0b00 << 2,
+            Cycle::Cycle255 => 0b00 << 2 | 1,
+            Cycle::Cycle128 => 0b01 << 2,
+            Cycle::Cycle127 => 0b01 << 2 | 1,
+            Cycle::Cycle64 => 0b10 << 2,
+            Cycle::Cycle63 => 0b10 << 2 | 1,
+            Cycle::Cycle32 => 0b11 << 2,
+            Cycle::Cycle31 => 0b11 << 2 | 1,
+        }
+    }
+
+    /// Number of PWM clocks in one cycle
+    pub fn clocks(self) -> u16 {
+        match self {
+            Cycle::Cycle256 => 256,
+            Cycle::Cycle255 => 255,
+            Cycle::Cycle128 => 128,
+            Cycle::Cycle127 => 127,
+            Cycle::Cycle64 => 64,
+            Cycle::Cycle63 => 63,
+            Cycle::Cycle32 => 32,
+            Cycle::Cycle31 => 31,
+        }
+    }
+}
+
+/// Output polarity
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Polarity {
+    /// Idle low, duty is high level
+    #[default]
+    ActiveHigh,
+    /// Idle high, duty is low level
+    ActiveLow,
+}
+
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Config {
+    /// PWM clock = Fsys / clock_div, 1 to 256
+    pub clock_div: u16,
+    pub cycle: Cycle,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            clock_div: 4,
+            cycle: Cycle::Cycle256,
+        }
+    }
+}
+
+impl Config {
+    /// Find the clock divider for a target PWM frequency
+    pub fn with_frequency(freq: Hertz, cycle: Cycle) -> Self {
+        let hclk = crate::sysctl::clocks().hclk.to_Hz();
+        let div = hclk / (freq.to_Hz() * cycle.clocks() as u32).max(1);
+        Self {
+            clock_div: div.clamp(1, 256) as u16,
+            cycle,
+        }
+    }
+}
+
+/// PWMX driver, owns the shared clock and cycle configuration.
+pub struct Pwm<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    cycle: Cycle,
+    /// Channels in use, by [`Channel`] bit
+    used: Cell<u8>,
+    /// Remap of the remappable channels in use
+    remap: Cell<Option<bool>>,
+}
+
+impl<'d, T: Instance> Pwm<'d, T> {
+    pub fn new(peri: impl Peripheral<P = T> + 'd, config: Config) -> Self {
+        into_ref!(peri);
+
+        <T as crate::rcc::sealed::RccPeripheral>::enable();
+
+        let rb = T::regs();
+        rb.pwm_ctrl.write(|w| unsafe { w.bits(0) });
+        rb.pwm_int_ctrl.write(|w| unsafe { w.bits(RB_PWM_IF_CYC) });
+
+        let mut this = Self {
+            _peri: peri,
+            cycle: config.cycle,
+            used: Cell::new(0),
+            remap: Cell::new(None),
+        };
+        this.set_config(config);
+        this
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        // 0 means 256
+        let div = config.clock_div.clamp(1, 256) & 0xff;
+        T::regs().pwm_cycle.modify(|r, w| unsafe {
+            let config_bits = r.bits() & 0xff & !RB_PWM_CYCLE_MASK;
+            w.bits((div << PWM_CLOCK_DIV_SHIFT) | config_bits | config.cycle.bits())
+        });
+        self.cycle = config.cycle;
+    }
+
+    pub fn cycle(&self) -> Cycle {
+        self.cycle
+    }
+
+    /// Current PWM frequency
+    pub fn frequency(&self) -> Hertz {
+        let div = match T::regs().pwm_cycle.read().bits() >> PWM_CLOCK_DIV_SHIFT {
+            0 => 256,
+            n => n as u32,
+        };
+        Hertz::from_raw(crate::sysctl::clocks().hclk.to_Hz() / div / self.cycle.clocks() as u32)
+    }
+
+    /// Enable or disable staggered output for a channel pair.
+    pub fn set_staggered(&self, pair: ChannelPair, enable: bool) {
+        let mask = 1 << (RB_PWM_STAG_EN_SHIFT + pair as u16);
+        T::regs().pwm_cycle.modify(|r, w| unsafe {
+            if enable {
+                w.bits(r.bits() | mask)
+            } else {
+                w.bits(r.bits() & !mask)
+            }
+        });
+    }
+
+    /// Enable cycle end interrupt.
+    /// `pre` to raise the interrupt one cycle earlier, so that duty can be updated in time.
+    pub fn enable_interrupt(&self, pre: bool) {
+        let pre = if pre { RB_PWM_CYC_PRE } else { 0 };
+        T::regs().pwm_int_ctrl.write(|w| unsafe { w.bits(RB_PWM_IE_CYC | pre) });
+    }
+
+    pub fn disable_interrupt(&self) {
+        T::regs()
+            .pwm_int_ctrl
+            .modify(|r, w| unsafe { w.bits(r.bits() & !RB_PWM_IE_CYC) });
+    }
+
+    pub fn is_pending(&self) -> bool {
+        T::regs().pwm_int_ctrl.read().bits() & RB_PWM_IF_CYC != 0
+    }
+
+    /// Call this in IRQ handler, to clear flag
+    pub fn clear_pending(&self) {
+        T::regs()
+            .pwm_int_ctrl
+            .modify(|r, w| unsafe { w.bits(r.bits() | RB_PWM_IF_CYC) });
+    }
+
+    fn channel<'a>(
+        &'a self,
+        ch: Channel,
+        pin: PeripheralRef<'a, impl crate::gpio::Pin>,
+        remap: Option<bool>,
+        polarity: Polarity,
+    ) -> Result<PwmChannel<'a, T>, Error> {
+        let bit = ch.mask() as u8;
+        if self.used.get() & bit != 0 {
+            return Err(Error::ChannelInUse);
+        }
+        if let Some(remap) = remap {
+            match self.remap.get() {
+                Some(current) if current != remap => return Err(Error::RemapConflict),
+                Some(_) => {}
+                None => {
+                    T::set_remap(remap);
+                    self.remap.set(Some(remap));
+                }
+            }
+        }
+        self.used.set(self.used.get() | bit);
+
+        match polarity {
+            Polarity::ActiveHigh => pin.set_low(),
+            Polarity::ActiveLow => pin.set_high(),
+        }
+        pin.set_as_output_with_drive_low();
+
+        write_duty::<T>(ch, 0);
+        critical_section::with(|_| {
+            T::regs().pwm_ctrl.modify(|r, w| unsafe {
+                let polar = match polarity {
+                    Polarity::ActiveHigh => r.bits() & !ch.polar_mask(),
+                    Polarity::ActiveLow => r.bits() | ch.polar_mask(),
+                };
+                w.bits(polar | ch.mask())
+            });
+        });
+
+        Ok(PwmChannel {
+            pwm: self,
+            _pin: pin.map_into(),
+            ch,
+            max_duty: self.cycle.clocks(),
+        })
+    }
+
+    /// Called when a channel is dropped
+    fn release(&self, ch: Channel) {
+        let used = self.used.get() & !(ch.mask() as u8);
+        self.used.set(used);
+        if used & REMAPPABLE == 0 {
+            self.remap.set(None);
+        }
+    }
+}
+
+macro_rules! impl_channel_ctor {
+    ($($fn_name:ident, $ch:ident, $pin_trait:ident, $remappable:expr;)*) => {
+        impl<'d, T: Instance> Pwm<'d, T> {
+            $(
+                pub fn $fn_name<'a>(
+                    &'a self,
+                    pin: impl Peripheral<P = impl $pin_trait<T>> + 'a,
+                    polarity: Polarity,
+                ) -> Result<PwmChannel<'a, T>, Error> {
+                    into_ref!(pin);
+                    let remap = if $remappable { Some(pin.is_remap()) } else { None };
+                    self.channel(Channel::$ch, pin, remap, polarity)
+                }
+            )*
+        }
+    };
+}
+
+impl_channel_ctor!(
+    channel4, Ch4, Pwm4Pin, true;
+    channel5, Ch5, Pwm5Pin, true;
+    channel6, Ch6, Pwm6Pin, false;
+    channel7, Ch7, Pwm7Pin, true;
+    channel8, Ch8, Pwm8Pin, true;
+    channel9, Ch9, Pwm9Pin, true;
+    channel10, Ch10, Pwm10Pin, false;
+    channel11, Ch11, Pwm11Pin, false;
+);
+
+impl<'d, T: Instance> Drop for Pwm<'d, T> {
+    fn drop(&mut self) {
+        let rb = T::regs();
+        rb.pwm_ctrl.modify(|r, w| unsafe { w.bits(r.bits() & !0xff) });
+        rb.pwm_int_ctrl.write(|w| unsafe { w.bits(RB_PWM_IF_CYC) });
+        <T as crate::rcc::sealed::RccPeripheral>::disable();
+    }
+}
+
+/// A single PWM output channel, borrowed from [`Pwm`]
+pub struct PwmChannel<'a, T: Instance> {
+    pwm: &'a Pwm<'a, T>,
+    _pin: PeripheralRef<'a, crate::gpio::AnyPin>,
+    ch: Channel,
+    max_duty: u16,
+}
+
+impl<'d, T: Instance> PwmChannel<'d, T> {
+    pub fn channel(&self) -> Channel {
+        self.ch
+    }
+
+    /// Max duty, equals to the cycle when this channel was created
+    pub fn max_duty(&self) -> u16 {
+        self.max_duty
+    }
+
+    /// Set duty in PWM clocks, 0 to max duty. Cycle 256 can not reach 100%.
+    pub fn set_duty(&mut self, duty: u16) {
+        let duty = duty.min(self.max_duty).min(0xff) as u8;
+        write_duty::<T>(self.ch, duty);
+    }
+
+    pub fn duty(&self) -> u16 {
+        read_duty::<T>(self.ch) as u16
+    }
+
+    pub fn enable(&mut self) {
+        critical_section::with(|_| {
+            T::regs()
+                .pwm_ctrl
+                .modify(|r, w| unsafe { w.bits(r.bits() | self.ch.mask()) })
+        });
+    }
+
+    pub fn disable(&mut self) {
+        critical_section::with(|_| {
+            T::regs()
+                .pwm_ctrl
+                .modify(|r, w| unsafe { w.bits(r.bits() & !self.ch.mask()) })
+        });
+    }
+
+    pub fn set_polarity(&mut self, polarity: Polarity) {
+        critical_section::with(|_| {
+            T::regs().pwm_ctrl.modify(|r, w| unsafe {
+                match polarity {
+                    Polarity::ActiveHigh => w.bits(r.bits() & !self.ch.polar_mask()),
+                    Polarity::ActiveLow => w.bits(r.bits() | self.ch.polar_mask()),
+                }
+            })
+        });
+    }
+}
+
+impl<'d, T: Instance> Drop for PwmChannel<'d, T> {
+    fn drop(&mut self) {
+        self.disable();
+        self.pwm.release(self.ch);
+    }
+}
+
+mod eh02 {
+    use super::*;
+
+    impl<'d, T: Instance> embedded_hal_02::PwmPin for PwmChannel<'d, T> {
+        type Duty = u16;
+
+        fn disable(&mut self) {
+            self.disable()
+        }
+
+        fn enable(&mut self) {
+            self.enable()
+        }
+
+        fn get_duty(&self) -> Self::Duty {
+            self.duty()
+        }
+
+        fn get_max_duty(&self) -> Self::Duty {
+            self.max_duty()
+        }
+
+        fn set_duty(&mut self, duty: Self::Duty) {
+            self.set_duty(duty)
+        }
+    }
+}
+
+mod eh1 {
+    use core::convert::Infallible;
+
+    use super::*;
+
+    impl<'d, T: Instance> embedded_hal_1::pwm::ErrorType for PwmChannel<'d, T> {
+        type Error = Infallible;
+    }
+
+    impl<'d, T: Instance> embedded_hal_1::pwm::SetDutyCycle for PwmChannel<'d, T> {
+        fn get_max_duty_cycle(&self) -> u16 {
+            self.max_duty()
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+            self.set_duty(duty);
+            Ok(())
+        }
+    }
+}
+
+macro_rules! duty_regs {
+    ($($ch:ident => $reg:ident,)*) => {
+        fn write_duty<T: Instance>(ch: Channel, duty: u8) {
+            let rb = T::regs();
+            match ch {
+                $(Channel::$ch => rb.$reg.write(|w| unsafe { w.bits(duty) }),)*
+            }
+        }
+
+        fn read_duty<T: Instance>(ch: Channel) -> u8 {
+            let rb = T::regs();
+            match ch {
+                $(Channel::$ch => rb.$reg.read().bits(),)*
+            }
+        }
+    };
+}
+
+duty_regs!(
+    Ch4 => pwm4_data,
+    Ch5 => pwm5_data,
+    Ch6 => pwm6_data,
+    Ch7 => pwm7_data,
+    Ch8 => pwm8_data,
+    Ch9 => pwm9_data,
+    Ch10 => pwm10_data,
+    Ch11 => pwm11_data,
+);
+
+// - instance trait
+
+pub(crate) mod sealed {
+    pub trait Instance {
+        type Interrupt: crate::interrupt::Interrupt;
+
+        fn regs() -> &'static crate::pac::pwmx::RegisterBlock;
+
+        /// Remap bit in R16_PIN_ALTERNATE
+        fn set_remap(enable: bool);
+    }
+}
+
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + crate::rcc::RccPeripheral {}
+
+impl sealed::Instance for peripherals::PWMX {
+    type Interrupt = crate::interrupt::PWMX;
+
+    fn regs() -> &'static crate::pac::pwmx::RegisterBlock {
+        unsafe { &*pac::PWMX::PTR }
+    }
+
+    fn set_remap(enable: bool) {
+        let gpioctl = unsafe { &*pac::GPIOCTL::PTR };
+        gpioctl.pin_alternate.modify(|_, w| w.pwmx().bit(enable));
+    }
+}
+impl Instance for peripherals::PWMX {}
+
+pin_trait!(Pwm4Pin, Instance);
+pin_trait!(Pwm5Pin, Instance);
+pin_trait!(Pwm6Pin, Instance);
+pin_trait!(Pwm7Pin, Instance);
+pin_trait!(Pwm8Pin, Instance);
+pin_trait!(Pwm9Pin, Instance);
+pin_trait!(Pwm10Pin, Instance);
+pin_trait!(Pwm11Pin, Instance);
@@ -0,0 +1,100 @@
+//! Peripheral clock gating
+//!
+//! `SLP_CLK_OFF0` and `SLP_CLK_OFF1` gate the clock of each peripheral, a set bit stops the clock.
+//! Drivers enable the clock in their constructors and gate it again on drop.
+
+use crate::{pac, peripherals, with_safe_access};
+
+pub(crate) mod sealed {
+    pub trait RccPeripheral {
+        /// Ungate the peripheral clock
+        fn enable();
+        /// Gate the peripheral clock
+        fn disable();
+        /// There's no per-peripheral reset line, this gates and ungates the clock.
+        /// Drivers clear registers themselves, e.g. `ALL_CLEAR` bits.
+        fn reset() {
+            Self::disable();
+            Self::enable();
+        }
+    }
+}
+
+pub trait RccPeripheral: sealed::RccPeripheral + 'static {}
+
+#[derive(Clone, Copy)]
+enum Reg {
+    Off0,
+    Off1,
+}
+
+fn set_gated(reg: Reg, mask: u8, gated: bool) {
+    let sys = unsafe { &*pac::SYS::PTR };
+
+    with_safe_access(|| unsafe {
+        match reg {
+            Reg::Off0 => sys.slp_clk_off0.modify(|r, w| {
+                if gated {
+                    w.bits(r.bits() | mask)
+                } else {
+                    w.bits(r.bits() & !mask)
+                }
+            }),
+            Reg::Off1 => sys.slp_clk_off1.modify(|r, w| {
+                if gated {
+                    w.bits(r.bits() | mask)
+                } else {
+                    w.bits(r.bits() & !mask)
+                }
+            }),
+        }
+    });
+}
+
+macro_rules! impl_rcc {
+    ($peri:ident, $reg:ident, $mask:expr) => {
+        impl sealed::RccPeripheral for peripherals::$peri {
+            fn enable() {
+                set_gated(Reg::$reg, $mask, false);
+            }
+            fn disable() {
+                set_gated(Reg::$reg, $mask, true);
+            }
+        }
+        impl RccPeripheral for peripherals::$peri {}
+    };
+    // always clocked
+    ($peri:ident) => {
+        impl sealed::RccPeripheral for peripherals::$peri {
+            fn enable() {}
+            fn disable() {}
+        }
+        impl RccPeripheral for peripherals::$peri {}
+    };
+}
+
+impl_rcc!(TMR0, Off0, 0x01);
+impl_rcc!(TMR1, Off0, 0x02);
+impl_rcc!(TMR2, Off0, 0x04);
+impl_rcc!(TMR3, Off0, 0x08);
+impl_rcc!(UART0, Off0, 0x10);
+impl_rcc!(UART1, Off0, 0x20);
+impl_rcc!(UART2, Off0, 0x40);
+impl_rcc!(UART3, Off0, 0x80);
+
+impl_rcc!(SPI0, Off1, 0x01);
+// SPI1 0x02, CH583 only
+impl_rcc!(PWMX, Off1, 0x04);
+impl_rcc!(I2C, Off1, 0x08);
+impl_rcc!(USB, Off1, 0x10);
+// LCD 0x40, see `lcd.rs`
+impl_rcc!(BLE, Off1, 0x80);
+
+#[cfg(not(feature = "time-driver-systick"))]
+impl_rcc!(SYSTICK);
+impl_rcc!(RTC);
+impl_rcc!(GPIO);
+impl_rcc!(ADC);
+impl_rcc!(TKEY);
+impl_rcc!(WDOG);
+impl_rcc!(BAT);
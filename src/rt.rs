@@ -1,11 +1,15 @@
 //! rt for CH58x
+//!
+//! The vector table and startup code are only built for RISC-V, [`Interrupt`] is also used by host builds.
 
+#[cfg(target_arch = "riscv32")]
 use core::arch::global_asm;
 
 #[export_name = "error: riscv-rt appears more than once in the dependency graph"]
 #[doc(hidden)]
 pub static __ONCE__: () = ();
 
+#[cfg(target_arch = "riscv32")]
 #[doc(hidden)]
 pub union Vector {
     handler: unsafe extern "C" fn(),
@@ -75,6 +79,7 @@ pub enum Interrupt {
 }
 
 // Overwrites PAC's interrupt handlers
+#[cfg(target_arch = "riscv32")]
 extern "C" {
     fn Reset() -> !;
 
@@ -114,6 +119,7 @@ extern "C" {
     fn WDOG_BAT();
 }
 
+#[cfg(target_arch = "riscv32")]
 #[doc(hidden)]
 #[link_section = ".vector_table.interrupts"]
 #[no_mangle]
@@ -167,6 +173,7 @@ pub static __INTERRUPTS: [Vector; 36] = [
     Vector { reserved: 0xaaaaaaaa },
 ];
 
+#[cfg(target_arch = "riscv32")]
 macro_rules! cfg_global_asm {
     {@inner, [$($x:tt)*], } => {
         global_asm!{$($x)*}
@@ -185,6 +192,7 @@ macro_rules! cfg_global_asm {
     };
 }
 
+#[cfg(target_arch = "riscv32")]
 cfg_global_asm! {
     "
     .section    .init,\"ax\"
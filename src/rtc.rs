@@ -1,12 +1,23 @@
 //! Real time clock
+//!
+//! The counter runs on the 32K clock: `cnt_day`, `cnt_2s` and `cnt_32k`.
+//! Timing mode raises a periodic event, trigger mode raises an event when `cnt_2s:cnt_32k` matches
+//! the `trig` register, i.e. an alarm at a time of day.
 
-use core::fmt;
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
 
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::Interrupt;
 use crate::peripherals::RTC;
 use crate::{pac, with_safe_access, Peripheral};
 
-// avoid cross 100-year, which is complex to handle leap year
-const YEAR_OFFSET: u16 = 2020;
+use self::calendar::UNIX_OFFSET_DAYS;
+pub use self::calendar::{days_in_month, is_leap_year, DateTime, Error};
+
+mod calendar;
 
 /// RTC timing mode fixed cycle
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -21,23 +32,36 @@ pub enum TimingMode {
     _16S = 0b111,
 }
 
+/// 32K ticks in one day, the `cnt_2s:cnt_32k` counter wraps here
+pub const TICKS_PER_DAY: u32 = 0xA8C0_0000;
+
+static ALARM_WAKER: AtomicWaker = AtomicWaker::new();
+static ALARM_FIRED: AtomicBool = AtomicBool::new(false);
+
+/// RTC interrupt events, both may be set in one interrupt
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Events {
+    /// Timing mode, periodic
+    pub timing: bool,
+    /// Trigger mode, alarm
+    pub trigger: bool,
+}
+
 /// RTC Abstraction
 pub struct Rtc;
 
 impl Rtc {
     pub fn new(_rtc: impl Peripheral<P = RTC>) -> Self {
-        // <RTC as crate::rcc::sealed::RccPeripheral>::enable();
+        <RTC as crate::rcc::sealed::RccPeripheral>::enable();
         Self {}
     }
-    pub fn timestamp_since_epoch(&self) -> u32 {
-        let rtc = unsafe { &*pac::RTC::PTR };
 
-        let day = rtc.cnt_day.read().bits() & 0x3fff;
-        let mut sec = rtc.cnt_2s.read().bits() * 2;
-        if rtc.cnt_32k.read().bits() >= 0x8000 {
-            sec += 1;
-        }
-        day * 86400 + (sec as u32)
+    /// Unix timestamp in seconds
+    pub fn timestamp_since_epoch(&self) -> u32 {
+        let (days, ticks) = self.read_counter();
+        let sec = (ticks >> 16) * 2 + ((ticks >> 15) & 1);
+        (UNIX_OFFSET_DAYS as u32 + days) * 86400 + sec
     }
 
     /// 32K clock tick
@@ -78,23 +102,115 @@ impl Rtc {
         });
     }
 
+    /// Set alarm at the time of day of `t`, the date is ignored.
+    ///
+    /// The alarm fires once every day until [`Rtc::disable_alarm`].
+    /// `trig` is shared with counter loading, [`Rtc::set_datatime`] disables the alarm.
+    pub fn set_alarm(&mut self, t: DateTime) {
+        let sec = (t.hour as u32 % 24) * 3600 + (t.minute as u32) * 60 + (t.second as u32);
+        let ticks = (sec / 2) << 16 | if sec & 1 != 0 { 0x8000 } else { 0 };
+        self.set_alarm_ticks(ticks);
+    }
+
+    /// Set alarm at a raw `cnt_2s:cnt_32k` value, wraps at [`TICKS_PER_DAY`]
+    pub fn set_alarm_ticks(&mut self, ticks: u32) {
+        let rtc = unsafe { &*pac::RTC::PTR };
+        let ticks = ticks % TICKS_PER_DAY;
+
+        ALARM_FIRED.store(false, Ordering::Relaxed);
+        rtc.flag_ctrl.modify(|_, w| w.trig_clr().set_bit());
+        with_safe_access(|| unsafe {
+            rtc.trig.write(|w| w.bits(ticks));
+            rtc.mode_ctrl.modify(|_, w| w.trig_en().set_bit());
+        });
+    }
+
+    /// Set alarm `ticks` 32K ticks from now
+    pub fn set_alarm_after(&mut self, ticks: u32) {
+        let now = self.counter_tick();
+        self.set_alarm_ticks(((now as u64 + ticks as u64) % TICKS_PER_DAY as u64) as u32);
+    }
+
+    pub fn disable_alarm(&mut self) {
+        let rtc = unsafe { &*pac::RTC::PTR };
+        with_safe_access(|| {
+            rtc.mode_ctrl.modify(|_, w| w.trig_en().clear_bit());
+        });
+        rtc.flag_ctrl.modify(|_, w| w.trig_clr().set_bit());
+    }
+
+    pub fn is_alarm_pending(&self) -> bool {
+        let rtc = unsafe { &*pac::RTC::PTR };
+        rtc.flag_ctrl.read().trig_flag().bit_is_set()
+    }
+
+    /// Call this in IRQ handler, to clear flag
+    pub fn ack_alarm(&mut self) {
+        let rtc = unsafe { &*pac::RTC::PTR };
+        rtc.flag_ctrl.modify(|_, w| w.trig_clr().set_bit()); // clear flag
+    }
+
+    /// Wait for the alarm set by [`Rtc::set_alarm`].
+    ///
+    /// Enables the `RTC` interrupt, [`Rtc::on_interrupt`] must be called from its handler.
+    pub async fn wait_alarm(&mut self) {
+        unsafe { crate::interrupt::RTC::enable() };
+
+        poll_fn(|cx| {
+            ALARM_WAKER.register(cx.waker());
+
+            if ALARM_FIRED.swap(false, Ordering::AcqRel) {
+                return Poll::Ready(());
+            }
+            // fired before the interrupt was enabled
+            if self.is_alarm_pending() {
+                self.ack_alarm();
+                return Poll::Ready(());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// RTC interrupt dispatcher, call this in the `RTC` IRQ handler.
+    ///
+    /// Clears the pending flags and wakes [`Rtc::wait_alarm`].
+    pub fn on_interrupt() -> Events {
+        let rtc = unsafe { &*pac::RTC::PTR };
+        let flags = rtc.flag_ctrl.read();
+
+        let events = Events {
+            timing: flags.tmr_flag().bit_is_set(),
+            trigger: flags.trig_flag().bit_is_set(),
+        };
+
+        rtc.flag_ctrl.modify(|_, w| {
+            w.tmr_clr().bit(events.timing);
+            w.trig_clr().bit(events.trigger)
+        });
+
+        if events.trigger {
+            ALARM_FIRED.store(true, Ordering::Release);
+            ALARM_WAKER.wake();
+        }
+
+        events
+    }
+
     // 32768
     pub fn counter_32k(&self) -> u16 {
         let rtc = unsafe { &*pac::RTC::PTR };
         rtc.cnt_32k.read().bits()
     }
 
-    pub fn set_datatime(&mut self, t: DateTime) {
+    /// Set the counter to `t`, must be within the counter range, 2020-01-01 to 2064-11-08.
+    pub fn set_datatime(&mut self, t: DateTime) -> Result<(), Error> {
         let rtc = unsafe { &*pac::RTC::PTR };
 
-        let mut days: u16 = (YEAR_OFFSET..t.year).map(|y| if y % 4 == 0 { 366 } else { 365 }).sum();
-        days += (1..=t.month - 1).map(|m| days_in_month(m, t.year)).sum::<u16>();
-        days += t.day as u16 - 1;
-
-        let sec2 = ((t.hour as u16) % 24) * 1800 + (t.minute as u16) * 30 + (t.second as u16) / 2;
-        let t32k: u16 = if t.second & 1 != 0 { 0x8000 } else { 0 };
+        let (days, sec2, t32k) = calendar::to_counter(&t)?;
 
         with_safe_access(|| unsafe {
+            rtc.mode_ctrl.modify(|_, w| w.trig_en().clear_bit());
             rtc.trig.write(|w| w.bits(days as u32));
             rtc.mode_ctrl.modify(|_, w| w.load_hi().set_bit());
         });
@@ -105,126 +221,40 @@ impl Rtc {
             rtc.trig.write(|w| w.bits(t));
             rtc.mode_ctrl.modify(|_, w| w.load_lo().set_bit());
         });
-    }
-
-    pub fn now(&self) -> DateTime {
-        let rtc = unsafe { &*pac::RTC::PTR };
-
-        let mut days = (rtc.cnt_day.read().bits() & 0x3fff) as u16;
-        // to u32, avoid overflow
-        let mut sec = (rtc.cnt_2s.read().bits() as u32) * 2;
-        if rtc.cnt_32k.read().bits() >= 0x8000 {
-            sec += 1;
-        }
-
-        let mut year: u16 = YEAR_OFFSET;
-        while days >= days_in_year(year) {
-            days -= days_in_year(year);
-            year += 1;
-        }
-
-        let mut month = 1;
-        while days >= days_in_month(month, year) {
-            days -= days_in_month(month, year);
-            month += 1;
-        }
 
-        let hour = (sec / 3600) as u8;
-        sec %= 3600;
-        let minute = (sec / 60) as u8;
-        sec %= 60;
-        let second = (sec) as u8;
-
-        DateTime {
-            year,
-            month,
-            day: (days + 1) as u8,
-            hour,
-            minute,
-            second,
-        }
+        Ok(())
     }
-}
 
-fn days_in_year(year: u16) -> u16 {
-    if year % 4 == 0 {
-        366
-    } else {
-        365
-    }
-}
-fn days_in_month(month: u8, year: u16) -> u16 {
-    match month {
-        1 => 31,
-        2 => {
-            if year % 4 == 0 {
-                29
-            } else {
-                28
-            }
-        }
-        3 => 31,
-        4 => 30,
-        5 => 31,
-        6 => 30,
-        7 => 31,
-        8 => 31,
-        9 => 30,
-        10 => 31,
-        11 => 30,
-        12 => 31,
-        _ => 0,
+    /// Set the counter from a Unix timestamp
+    pub fn set_unix(&mut self, secs: u64) -> Result<(), Error> {
+        self.set_datatime(DateTime::from_unix(secs))
     }
-}
 
-/// Structure containing date and time information
-#[derive(Copy, Clone, PartialEq, Debug)]
-pub struct DateTime {
-    /// 2020..2064
-    pub year: u16,
-    /// 1..12, 1 is January
-    pub month: u8,
-    /// 1..28,29,30,31 depending on month
-    pub day: u8,
-    /// 0..23
-    pub hour: u8,
-    /// 0..59
-    pub minute: u8,
-    /// 0..59
-    pub second: u8,
-}
+    pub fn now(&self) -> DateTime {
+        DateTime::from_unix(self.timestamp_since_epoch() as u64)
+    }
 
-impl DateTime {
-    /// Return the day of the week as an integer, where Monday is 0 and Sunday is 6
-    pub fn weekday(&self) -> u8 {
-        let mut y = self.year as u32;
-        let m = self.month as u32;
-        if m < 3 {
-            y -= 1;
-        }
-        let d = self.day as u32;
-        let c = y / 100;
-        let y = y % 100;
-        let w = (c / 4 - 2 * c + y + y / 4 + 13 * (m + 1) / 5 + d - 1) % 7;
-        if w == 0 {
-            6
-        } else {
-            (w - 1) as _
-        }
+    /// Milliseconds within the current second, from `cnt_32k`
+    pub fn subsec_millis(&self) -> u16 {
+        let (_, ticks) = self.read_counter();
+        ((ticks & 0x7fff) * 1000 / 32768) as u16
     }
 
-    /// Return the day of the week as an integer, where Monday is 1 and Sunday is 7
-    pub fn isoweekday(&self) -> u8 {
-        self.weekday() + 1
+    /// Unix timestamp in milliseconds
+    pub fn timestamp_millis(&self) -> u64 {
+        let (days, ticks) = self.read_counter();
+        let ms = (ticks >> 16) as u64 * 2000 + (ticks & 0xffff) as u64 * 1000 / 32768;
+        (UNIX_OFFSET_DAYS + days as u64) * 86_400_000 + ms
     }
-}
 
-impl fmt::Display for DateTime {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
-            self.year, self.month, self.day, self.hour, self.minute, self.second
-        )
+    /// Read day and `cnt_2s:cnt_32k` consistently, retry when a counter carries in between
+    fn read_counter(&self) -> (u32, u32) {
+        loop {
+            let days = self.counter_day() as u32;
+            let ticks = self.counter_tick();
+            if days == self.counter_day() as u32 && ticks >> 16 == self.counter_2s() as u32 {
+                return (days, ticks);
+            }
+        }
     }
 }
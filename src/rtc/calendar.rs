@@ -0,0 +1,246 @@
+//! Gregorian calendar and RTC counter conversions, free of register access
+
+use core::fmt;
+
+/// Day 0 of the counter
+pub(crate) const YEAR_OFFSET: u16 = 2020;
+/// 1970-01-01 to 2020-01-01
+pub(crate) const UNIX_OFFSET_DAYS: u64 = 18262;
+/// 14-bit day counter
+pub(crate) const MAX_DAYS: u16 = 0x4000;
+
+/// RTC error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Field out of range, e.g. month 13 or Feb 30
+    InvalidDateTime,
+    /// Not representable by the 14-bit day counter
+    OutOfRange,
+}
+
+pub fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub fn days_in_month(month: u8, year: u16) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+///
+/// From <http://howardhinnant.github.io/date_algorithms.html>
+fn days_from_civil(year: u16, month: u8, day: u8) -> i32 {
+    let y = year as i32 - (month <= 2) as i32;
+    let m = month as i32;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400; // [0, 399]
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + day as i32 - 1; // [0, 365]
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy; // [0, 146096]
+    era * 146097 + doe - 719468
+}
+
+/// Inverse of [`days_from_civil`], (year, month, day)
+fn civil_from_days(days: i32) -> (u16, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097; // [0, 146096]
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365; // [0, 399]
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100); // [0, 365]
+    let mp = (5 * doy + 2) / 153; // [0, 11]
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = (yoe + era * 400 + (month <= 2) as i32) as u16;
+    (year, month, day)
+}
+
+/// Structure containing date and time information
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DateTime {
+    /// 1970.., the RTC counter covers 2020..2064
+    pub year: u16,
+    /// 1..12, 1 is January
+    pub month: u8,
+    /// 1..28,29,30,31 depending on month
+    pub day: u8,
+    /// 0..23
+    pub hour: u8,
+    /// 0..59
+    pub minute: u8,
+    /// 0..59
+    pub second: u8,
+}
+
+impl DateTime {
+    /// From seconds since 1970-01-01 00:00:00 UTC
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86400) as i32;
+        let sec = (secs % 86400) as u32;
+        let (year, month, day) = civil_from_days(days);
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (sec / 3600) as u8,
+            minute: (sec % 3600 / 60) as u8,
+            second: (sec % 60) as u8,
+        }
+    }
+
+    /// To seconds since 1970-01-01 00:00:00 UTC, the date must be valid
+    pub fn to_unix(&self) -> u64 {
+        let days = days_from_civil(self.year, self.month, self.day) as u64;
+        days * 86400 + (self.hour as u64) * 3600 + (self.minute as u64) * 60 + self.second as u64
+    }
+
+    /// All fields in range, the date exists, and not before 1970
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.month, self.year)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Return the day of the week as an integer, where Monday is 0 and Sunday is 6
+    pub fn weekday(&self) -> u8 {
+        let mut y = self.year as u32;
+        let m = self.month as u32;
+        if m < 3 {
+            y -= 1;
+        }
+        let d = self.day as u32;
+        let c = y / 100;
+        let y = y % 100;
+        let w = (c / 4 - 2 * c + y + y / 4 + 13 * (m + 1) / 5 + d - 1) % 7;
+        if w == 0 {
+            6
+        } else {
+            (w - 1) as _
+        }
+    }
+
+    /// Return the day of the week as an integer, where Monday is 1 and Sunday is 7
+    pub fn isoweekday(&self) -> u8 {
+        self.weekday() + 1
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Counter value of `t`: (`cnt_day`, `cnt_2s`, `cnt_32k`)
+pub(crate) fn to_counter(t: &DateTime) -> Result<(u16, u16, u16), Error> {
+    if !t.is_valid() {
+        return Err(Error::InvalidDateTime);
+    }
+    let days = days_from_civil(t.year, t.month, t.day) - days_from_civil(YEAR_OFFSET, 1, 1);
+    if !(0..MAX_DAYS as i32).contains(&days) {
+        return Err(Error::OutOfRange);
+    }
+
+    let sec2 = (t.hour as u16) * 1800 + (t.minute as u16) * 30 + (t.second as u16) / 2;
+    let t32k: u16 = if t.second & 1 != 0 { 0x8000 } else { 0 };
+    Ok((days as u16, sec2, t32k))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn epoch() {
+        assert_eq!(DateTime::from_unix(0), dt(1970, 1, 1, 0, 0, 0));
+        assert_eq!(dt(1970, 1, 1, 0, 0, 0).to_unix(), 0);
+        assert_eq!(dt(2020, 1, 1, 0, 0, 0).to_unix(), UNIX_OFFSET_DAYS * 86400);
+        assert_eq!(to_counter(&dt(2020, 1, 1, 0, 0, 0)), Ok((0, 0, 0)));
+    }
+
+    #[test]
+    fn leap_centuries() {
+        assert!(is_leap_year(2000));
+        assert!(!is_leap_year(2100));
+        assert!(is_leap_year(2024));
+        assert!(!is_leap_year(2023));
+
+        assert_eq!(days_in_month(2, 2000), 29);
+        assert_eq!(days_in_month(2, 2100), 28);
+        assert_eq!(days_from_civil(2000, 3, 1) - days_from_civil(2000, 2, 28), 2);
+        assert_eq!(days_from_civil(2100, 3, 1) - days_from_civil(2100, 2, 28), 1);
+        assert_eq!(
+            DateTime::from_unix(dt(2100, 2, 28, 23, 59, 59).to_unix() + 1),
+            dt(2100, 3, 1, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn counter_limits() {
+        let max = dt(2064, 11, 8, 23, 59, 59);
+        assert_eq!(max.to_unix(), 2_993_414_399);
+        assert_eq!(to_counter(&max), Ok((MAX_DAYS - 1, 43199, 0x8000)));
+
+        assert_eq!(to_counter(&dt(2064, 11, 9, 0, 0, 0)), Err(Error::OutOfRange));
+        assert_eq!(to_counter(&dt(2019, 12, 31, 23, 59, 59)), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn round_trip_full_counter_range() {
+        for day in 0..MAX_DAYS as u64 {
+            // vary the time of day as well
+            let secs = (UNIX_OFFSET_DAYS + day) * 86400 + (day * 7919) % 86400;
+            let t = DateTime::from_unix(secs);
+            assert!(t.is_valid());
+            assert_eq!(t.to_unix(), secs);
+
+            let (cnt_day, sec2, t32k) = to_counter(&t).unwrap();
+            assert_eq!(cnt_day as u64, day);
+            assert_eq!(sec2 as u64 * 2 + (t32k != 0) as u64, secs % 86400);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        for t in [
+            dt(2024, 0, 1, 0, 0, 0),
+            dt(2024, 13, 1, 0, 0, 0),
+            dt(2024, 1, 0, 0, 0, 0),
+            dt(2024, 4, 31, 0, 0, 0),
+            dt(2023, 2, 29, 0, 0, 0),
+            dt(2024, 2, 30, 0, 0, 0),
+            dt(2024, 1, 1, 24, 0, 0),
+            dt(2024, 1, 1, 0, 60, 0),
+            dt(2024, 1, 1, 0, 0, 60),
+        ] {
+            assert!(!t.is_valid());
+            assert_eq!(to_counter(&t), Err(Error::InvalidDateTime));
+        }
+        assert!(dt(2024, 2, 29, 0, 0, 0).is_valid());
+    }
+}
@@ -147,11 +147,7 @@ impl<'d, T: Instance> Spi<'d, T> {
     ) -> Self {
         into_ref!(peri);
 
-        // TODO
-        if false {
-            let sys = unsafe { &*crate::pac::SYS::PTR };
-            sys.slp_clk_off1.modify(|_, w| w.slp_clk_spi0().clear_bit());
-        }
+        <T as crate::rcc::sealed::RccPeripheral>::enable();
 
         // set clock div
         let sysclk = crate::sysctl::clocks().hclk.to_Hz();
@@ -261,6 +257,12 @@ impl<'d, T: Instance> Spi<'d, T> {
     }
 }
 
+impl<'d, T: Instance> Drop for Spi<'d, T> {
+    fn drop(&mut self) {
+        <T as crate::rcc::sealed::RccPeripheral>::disable();
+    }
+}
+
 mod eh02 {
     use super::*;
 
@@ -283,7 +285,7 @@ pub(crate) mod sealed {
     }
 }
 
-pub trait Instance: Peripheral<P = Self> + sealed::Instance {}
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + crate::rcc::RccPeripheral {}
 
 impl sealed::Instance for peripherals::SPI0 {
     fn regs() -> &'static crate::pac::spi0::RegisterBlock {
@@ -6,14 +6,65 @@ use crate::with_safe_access;
 // No HSI
 const HSE_FREQUENCY: Hertz = Hertz::from_raw(32_000_000);
 const PLL_FREQUENCY: Hertz = Hertz::from_raw(480_000_000);
+const LSE_FREQUENCY: Hertz = Hertz::from_raw(32_768);
+const LSI_FREQUENCY: Hertz = Hertz::from_raw(32_768);
+/// USB PHY clock, PLL/10
+const USB_FREQUENCY: Hertz = Hertz::from_raw(48_000_000);
+/// Highest HCLK, PLL/6
+const MAX_HCLK: Hertz = Hertz::from_raw(80_000_000);
+
+// R8_XT32M_TUNE
+const XT32M_I_BIAS: u8 = 0x03;
+const XT32M_C_LOAD: u8 = 0x70;
+
+/// Last frozen config, re-applied after wakeup from sleep
+static mut CONFIG: Option<Config> = None;
 
 static mut CLOCK: Clocks = Clocks {
-    // Power on default
+    // Power on default, HSE/5
     hclk: Hertz::from_raw(6_400_000),
+    ck32k: LSI_FREQUENCY,
+    ck32k_source: Clock32KSrc::LSI,
+    hse: Some(HSE_FREQUENCY),
+    pll: None,
+    usb: None,
+    adc: Some(HSE_FREQUENCY),
 };
 
+/// Clock config error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockError {
+    /// No divider of HSE, PLL or CK32K gives this frequency
+    Unreachable,
+    /// Divider out of 2..=32, or HCLK above 80MHz
+    InvalidDivider,
+}
+
+/// HSE crystal bias current, relative to the default
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HseCurrent {
+    _75Percent = 0b00,
+    _100Percent = 0b01,
+    _125Percent = 0b10,
+    _150Percent = 0b11,
+}
+
+/// HSE crystal load capacitance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HseLoad {
+    _10pF = 0,
+    _12pF = 1,
+    _14pF = 2,
+    _16pF = 3,
+    _18pF = 4,
+    _20pF = 5,
+    _22pF = 6,
+    _24pF = 7,
+}
+
 /// 32K clock source
-#[derive(Clone, Copy, Debug, PartialEq, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub enum Clock32KSrc {
     #[default]
     LSI,
@@ -37,13 +88,71 @@ impl Default for ClockSrc {
     }
 }
 
+impl ClockSrc {
+    /// HCLK frequency, or an error for an out of range divider
+    pub fn frequency(&self, ck32k: Hertz) -> Result<Hertz, ClockError> {
+        match *self {
+            ClockSrc::Clock32K => Ok(ck32k),
+            ClockSrc::HSE(div) if (2..=32).contains(&div) => Ok(HSE_FREQUENCY / (div as u32)),
+            ClockSrc::PLL(div) if (2..=32).contains(&div) && PLL_FREQUENCY / (div as u32) <= MAX_HCLK => {
+                Ok(PLL_FREQUENCY / (div as u32))
+            }
+            _ => Err(ClockError::InvalidDivider),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Default)]
 pub struct Config {
     pub clock32ksrc: Clock32KSrc,
     pub mux: ClockSrc,
+    /// HSE bias current, `None` keeps the current setting
+    pub hse_current: Option<HseCurrent>,
+    /// HSE load capacitance, `None` keeps the current setting
+    pub hse_load: Option<HseLoad>,
 }
 
 impl Config {
+    /// Select the source and divider giving exactly `freq`.
+    ///
+    /// HSE is preferred over PLL for lower power, CK32K is used for 32768Hz.
+    pub fn hclk(&mut self, freq: Hertz) -> Result<&mut Self, ClockError> {
+        let hz = freq.to_Hz();
+        if hz == 0 || freq > MAX_HCLK {
+            return Err(ClockError::Unreachable);
+        }
+
+        self.mux = if freq == self.ck32k() {
+            ClockSrc::Clock32K
+        } else if HSE_FREQUENCY.to_Hz() % hz == 0 && (2..=32).contains(&(HSE_FREQUENCY.to_Hz() / hz)) {
+            ClockSrc::HSE((HSE_FREQUENCY.to_Hz() / hz) as u8)
+        } else if PLL_FREQUENCY.to_Hz() % hz == 0 && (2..=32).contains(&(PLL_FREQUENCY.to_Hz() / hz)) {
+            ClockSrc::PLL((PLL_FREQUENCY.to_Hz() / hz) as u8)
+        } else {
+            return Err(ClockError::Unreachable);
+        };
+        Ok(self)
+    }
+
+    /// Tune the HSE crystal oscillator, see the crystal datasheet for its load capacitance
+    pub fn hse_tune(&mut self, current: HseCurrent, load: HseLoad) -> &mut Self {
+        self.hse_current = Some(current);
+        self.hse_load = Some(load);
+        self
+    }
+
+    /// Check dividers
+    pub fn validate(&self) -> Result<(), ClockError> {
+        self.mux.frequency(self.ck32k()).map(|_| ())
+    }
+
+    fn ck32k(&self) -> Hertz {
+        match self.clock32ksrc {
+            Clock32KSrc::LSI => LSI_FREQUENCY,
+            Clock32KSrc::LSE => LSE_FREQUENCY,
+        }
+    }
+
     pub fn use_lsi_32k(&mut self) -> &mut Self {
         self.clock32ksrc = Clock32KSrc::LSI;
         self.mux = ClockSrc::Clock32K;
@@ -86,9 +195,25 @@ impl Config {
         self
     }
 
-    pub fn freeze(self) {
+    /// Apply the config. Fails on an invalid divider without touching the clocks, see [`Config::validate`]
+    pub fn freeze(self) -> Result<Clocks, ClockError> {
         let sys = unsafe { &*SYS::PTR };
 
+        self.validate()?;
+
+        if self.hse_current.is_some() || self.hse_load.is_some() {
+            let mut tune = sys.xt32m_tune.read().bits();
+            if let Some(current) = self.hse_current {
+                tune = (tune & !XT32M_I_BIAS) | current as u8;
+            }
+            if let Some(load) = self.hse_load {
+                tune = (tune & !XT32M_C_LOAD) | ((load as u8) << 4);
+            }
+            with_safe_access(|| unsafe {
+                sys.xt32m_tune.write(|w| w.bits(tune));
+            });
+        }
+
         match self.clock32ksrc {
             Clock32KSrc::LSE => {
                 with_safe_access(|| {
@@ -110,12 +235,37 @@ impl Config {
             _ => (),
         }
 
+        let hclk = self.freeze_sysclk();
+
+        let hfck = sys.hfck_pwr_ctrl.read();
+        let hse_on = hfck.clk_xt32m_pon().bit_is_set();
+        let pll_on = hfck.clk_pll_pon().bit_is_set();
+        let clocks = Clocks {
+            hclk,
+            ck32k: self.ck32k(),
+            ck32k_source: self.clock32ksrc,
+            hse: hse_on.then_some(HSE_FREQUENCY),
+            pll: pll_on.then_some(PLL_FREQUENCY),
+            usb: pll_on.then_some(USB_FREQUENCY),
+            adc: hse_on.then_some(HSE_FREQUENCY),
+        };
+
+        unsafe {
+            CONFIG = Some(self);
+            CLOCK = clocks;
+        }
+        Ok(clocks)
+    }
+
+    /// Switch HCLK to `mux`, powering on HSE or PLL if needed
+    fn freeze_sysclk(&self) -> Hertz {
+        let sys = unsafe { &*SYS::PTR };
+
         with_safe_access(|| unsafe {
             sys.pll_config.modify(|r, w| w.bits(r.bits() & !(1 << 5)));
         });
         let hclk = match self.mux {
             ClockSrc::HSE(div) => {
-                assert!(div != 1, "1 means close HCLK");
                 if sys.hfck_pwr_ctrl.read().clk_xt32m_pon().bit_is_clear() {
                     // HSE power on
                     with_safe_access(|| sys.hfck_pwr_ctrl.modify(|_, w| w.clk_xt32m_pon().set_bit()));
@@ -137,7 +287,6 @@ impl Config {
                 Hertz::from_raw(HSE_FREQUENCY.to_Hz() / (div as u32))
             }
             ClockSrc::PLL(div) => {
-                assert!(div != 1, "1 means close HCLK");
                 if sys.hfck_pwr_ctrl.read().clk_pll_pon().bit_is_clear() {
                     // HSE power on
                     with_safe_access(|| sys.hfck_pwr_ctrl.modify(|_, w| w.clk_pll_pon().set_bit()));
@@ -168,24 +317,204 @@ impl Config {
             _ => {
                 // directly from CK32
                 sys.clk_sys_cfg.modify(|r, w| unsafe { w.bits(r.bits() | 0xC0) });
-                Hertz::from_raw(32_768)
+                self.ck32k()
             }
         };
         with_safe_access(|| unsafe {
             sys.pll_config.modify(|r, w| w.bits(r.bits() | (1 << 7)));
         });
 
-        unsafe {
-            CLOCK = Clocks { hclk };
-        }
+        hclk
     }
 }
 
+/// Frozen clock tree, returned by [`crate::try_init`] and [`Config::freeze`]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Clocks {
     pub hclk: Hertz,
+    /// CK32K, clocks the RTC and low power timing
+    pub ck32k: Hertz,
+    pub ck32k_source: Clock32KSrc,
+    /// CK32M, `None` when powered off
+    pub hse: Option<Hertz>,
+    pub pll: Option<Hertz>,
+    /// USB PHY clock, from PLL
+    pub usb: Option<Hertz>,
+    /// ADC clock source, CK32M, divided by [`crate::adc::SamplingClock`]
+    pub adc: Option<Hertz>,
 }
 
 pub fn clocks() -> &'static Clocks {
     unsafe { &CLOCK }
 }
+
+/// Restore HCLK of the last [`Config::freeze`], after wakeup from sleep
+pub(crate) fn refreeze() {
+    if let Some(config) = unsafe { CONFIG } {
+        config.freeze_sysclk();
+    }
+}
+
+/// LSI calibration error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CalibrationError {
+    /// 32K clock is LSE, nothing to calibrate
+    NotLsi,
+    /// HCLK is not derived from HSE, there's no reference
+    NoReference,
+}
+
+/// Number of 32K cycles counted per measurement, longer is more precise.
+///
+/// `Cycles1024` overflows the counter above 80MHz HCLK.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum CalibrationLevel {
+    Cycles32 = 3,
+    #[default]
+    Cycles64 = 4,
+    Cycles128 = 5,
+    Cycles1024 = 6,
+}
+
+impl CalibrationLevel {
+    fn cycles(self) -> u32 {
+        match self {
+            Self::Cycles32 => 32,
+            Self::Cycles64 => 64,
+            Self::Cycles128 => 128,
+            Self::Cycles1024 => 1024,
+        }
+    }
+}
+
+/// Result of [`calibrate_lsi`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LsiCalibration {
+    /// Error before calibration, positive when LSI is fast
+    pub ppm_before: i32,
+    /// Error after calibration
+    pub ppm_after: i32,
+    /// Value written to INT32K_TUNE
+    pub tune: u16,
+}
+
+// R8_OSC_CAL_CTRL
+const OSC_CNT_TOTAL: u8 = 0x07;
+const OSC_CNT_HALT: u8 = 0x08;
+const OSC_CNT_EN: u8 = 0x20;
+// R16_OSC_CAL_CNT
+const OSC_CAL_CNT: u16 = 0x3fff;
+const OSC_CAL_OV_CLR: u16 = 0x4000;
+const OSC_CAL_IF: u16 = 0x8000;
+// R16_INT32K_TUNE, 13-bit
+const INT32K_TUNE_MAX: u16 = 0x1fff;
+
+/// Initial guess of the trim sensitivity, refined from measurements
+const PPM_PER_TUNE_STEP: i32 = 400;
+const MAX_ITERATIONS: usize = 8;
+
+/// Measure the LSI frequency error against HCLK, in ppm, positive when LSI is fast.
+pub fn measure_lsi_ppm(level: CalibrationLevel) -> Result<i32, CalibrationError> {
+    check_lsi_reference()?;
+    Ok(measure_lsi(level))
+}
+
+/// Trim the internal 32K oscillator against HSE, via the hardware calibration counter.
+///
+/// LSI drifts with temperature and voltage, call this periodically, e.g. every few minutes or
+/// on temperature change. HCLK must run from HSE or PLL.
+pub fn calibrate_lsi(level: CalibrationLevel) -> Result<LsiCalibration, CalibrationError> {
+    check_lsi_reference()?;
+
+    let sys = unsafe { &*SYS::PTR };
+
+    let mut tune = sys.int32k_tune.read().bits() & INT32K_TUNE_MAX;
+    let mut ppm = measure_lsi(level);
+    let ppm_before = ppm;
+
+    let mut best = (ppm, tune);
+    let mut slope = PPM_PER_TUNE_STEP;
+
+    for _ in 0..MAX_ITERATIONS {
+        // round to the nearest step, a higher tune value is a higher frequency
+        let step = (-ppm * 2 / slope + (-ppm).signum()) / 2;
+        if step == 0 {
+            break;
+        }
+        let new_tune = (tune as i32 + step).clamp(0, INT32K_TUNE_MAX as i32) as u16;
+        if new_tune == tune {
+            break;
+        }
+
+        with_safe_access(|| unsafe {
+            sys.int32k_tune.write(|w| w.bits(new_tune));
+        });
+        let new_ppm = measure_lsi(level);
+
+        let measured_slope = (new_ppm - ppm) / (new_tune as i32 - tune as i32);
+        if measured_slope > 0 {
+            slope = measured_slope;
+        }
+
+        tune = new_tune;
+        ppm = new_ppm;
+        if ppm.abs() < best.0.abs() {
+            best = (ppm, tune);
+        }
+    }
+
+    if best.1 != tune {
+        with_safe_access(|| unsafe {
+            sys.int32k_tune.write(|w| w.bits(best.1));
+        });
+    }
+
+    Ok(LsiCalibration {
+        ppm_before,
+        ppm_after: best.0,
+        tune: best.1,
+    })
+}
+
+fn check_lsi_reference() -> Result<(), CalibrationError> {
+    let sys = unsafe { &*SYS::PTR };
+
+    if sys.ck32k_config.read().clk_osc32k_xt().bit_is_set() {
+        return Err(CalibrationError::NotLsi);
+    }
+    // 0b1x: HCLK from CK32K
+    if sys.clk_sys_cfg.read().clk_sys_mod().bits() & 0b10 != 0 {
+        return Err(CalibrationError::NoReference);
+    }
+    Ok(())
+}
+
+/// Count HCLK cycles over `level` LSI cycles
+fn measure_lsi(level: CalibrationLevel) -> i32 {
+    let sys = unsafe { &*SYS::PTR };
+
+    with_safe_access(|| unsafe {
+        sys.osc_cal_ctrl
+            .modify(|r, w| w.bits((r.bits() & !OSC_CNT_TOTAL) | level as u8 | OSC_CNT_EN));
+    });
+    with_safe_access(|| unsafe {
+        sys.osc_cal_cnt
+            .modify(|r, w| w.bits(r.bits() | OSC_CAL_OV_CLR | OSC_CAL_IF));
+    });
+
+    // the first count after config is discarded
+    while sys.osc_cal_ctrl.read().bits() & OSC_CNT_HALT == 0 {}
+    while sys.osc_cal_ctrl.read().bits() & OSC_CNT_HALT != 0 {}
+
+    let count = (sys.osc_cal_cnt.read().bits() & OSC_CAL_CNT) as i64
+        + sys.osc_cal_ov_cnt.read().bits() as i64 * OSC_CAL_CNT as i64;
+
+    with_safe_access(|| unsafe {
+        sys.osc_cal_ctrl.modify(|r, w| w.bits(r.bits() & !OSC_CNT_EN));
+    });
+
+    let expected = level.cycles() as i64 * clocks().hclk.to_Hz() as i64 / LSI_FREQUENCY.to_Hz() as i64;
+    ((expected - count) * 1_000_000 / count) as i32
+}
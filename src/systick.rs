@@ -1,33 +1,37 @@
+//! SysTick, the 64-bit core timer of QingKe V4.
+//!
+//! [`SysTick`] is a blocking delay provider. [`SysTickMonotonic`] is an interrupt based monotonic
+//! clock, with `fugit` instant and duration types and RTIC `Monotonic` support (`rtic` feature).
+//!
+//! Both are unavailable with the `time-driver-systick` feature, the embassy time driver owns SysTick.
+
+use core::ptr;
+
 use crate::pac;
+#[cfg(not(feature = "time-driver-systick"))]
 use crate::peripheral::{Peripheral, PeripheralRef};
+#[cfg(not(feature = "time-driver-systick"))]
 use crate::peripherals::SYSTICK;
 
+/// Instant of a SysTick based monotonic clock
+pub type Instant<const TIMER_HZ: u32> = fugit::TimerInstantU64<TIMER_HZ>;
+/// Duration of a SysTick based monotonic clock
+pub type Duration<const TIMER_HZ: u32> = fugit::TimerDurationU64<TIMER_HZ>;
+
+#[cfg(not(feature = "time-driver-systick"))]
 pub struct SysTick<'d> {
     _inner: PeripheralRef<'d, SYSTICK>,
     ticks_per_second: u64,
 }
 
+#[cfg(not(feature = "time-driver-systick"))]
 impl<'d> SysTick<'d> {
     pub fn new(p: impl Peripheral<P = SYSTICK> + 'd) -> Self {
         crate::into_ref!(p);
 
-        let systick = unsafe { &*pac::SYSTICK::PTR };
-
         let ticks_per_second = crate::sysctl::clocks().hclk.to_Hz() as u64 / 8;
 
-        systick.ctlr.modify(|_, w| {
-            w.init()
-                .set_bit()
-                .mode()
-                .upcount()
-                .stre()
-                .clear_bit() // no reload
-                .stclk()
-                .hclk_div8()
-                //.stie().set_bit() // disable interrupt
-                .ste()
-                .set_bit()
-        });
+        start_counter(false);
 
         Self {
             _inner: p,
@@ -36,24 +40,182 @@ impl<'d> SysTick<'d> {
     }
 
     pub fn now() -> u64 {
-        let systick = unsafe { &*pac::SYSTICK::PTR };
-
-        systick.cnt.read().bits()
+        read_counter()
     }
 }
 
+#[cfg(not(feature = "time-driver-systick"))]
 impl<'d> embedded_hal_1::delay::DelayUs for SysTick<'d> {
     fn delay_us(&mut self, us: u32) {
         let us = self.ticks_per_second * (us as u64) / 1_000_000;
 
-        let target = Self::now() + us;
-        while Self::now() < target {}
+        let start = Self::now();
+        while Self::now().wrapping_sub(start) < us {}
     }
 
     fn delay_ms(&mut self, ms: u32) {
         let ms = self.ticks_per_second * (ms as u64) / 1_000;
 
-        let target = Self::now() + ms;
-        while Self::now() < target {}
+        let start = Self::now();
+        while Self::now().wrapping_sub(start) < ms {}
+    }
+}
+
+#[cfg(not(feature = "time-driver-systick"))]
+/// Monotonic clock on SysTick, counting HCLK/8.
+///
+/// `TIMER_HZ` must match the clock config, e.g. `7_500_000` for 60MHz HCLK.
+/// The counter is 64-bit, it will not overflow in the lifetime of the device.
+///
+/// The compare match raises the `SysTick` interrupt, call [`SysTickMonotonic::on_interrupt`] from its handler.
+pub struct SysTickMonotonic<const TIMER_HZ: u32> {
+    _inner: PeripheralRef<'static, SYSTICK>,
+}
+
+#[cfg(not(feature = "time-driver-systick"))]
+impl<const TIMER_HZ: u32> SysTickMonotonic<TIMER_HZ> {
+    pub fn new(p: impl Peripheral<P = SYSTICK> + 'static) -> Self {
+        crate::into_ref!(p);
+
+        assert_eq!(
+            crate::sysctl::clocks().hclk.to_Hz() / 8,
+            TIMER_HZ,
+            "TIMER_HZ must be HCLK/8"
+        );
+
+        write_compare(u64::MAX);
+        clear_flag();
+        start_counter(true);
+
+        Self { _inner: p }
+    }
+
+    pub fn now() -> Instant<TIMER_HZ> {
+        Instant::from_ticks(read_counter())
+    }
+
+    /// Fire the `SysTick` interrupt when counter reaches `instant`.
+    /// An instant in the past fires immediately.
+    pub fn set_compare(&mut self, instant: Instant<TIMER_HZ>) {
+        let now = read_counter();
+        let ticks = instant.ticks();
+        if ticks <= now {
+            write_compare(now + 1);
+        } else {
+            write_compare(ticks);
+        }
+    }
+
+    pub fn clear_compare(&mut self) {
+        write_compare(u64::MAX);
+        clear_flag();
+    }
+
+    pub fn is_pending(&self) -> bool {
+        let systick = unsafe { &*pac::SYSTICK::PTR };
+        systick.sr.read().cntif().bit_is_set()
+    }
+
+    /// Call this in IRQ handler, to clear flag
+    pub fn on_interrupt(&mut self) {
+        clear_flag();
+    }
+
+    pub fn enable_interrupt(&mut self) {
+        let systick = unsafe { &*pac::SYSTICK::PTR };
+        systick.ctlr.modify(|_, w| w.stie().set_bit());
+        unsafe { <crate::interrupt::SysTick as crate::interrupt::Interrupt>::enable() };
+    }
+
+    pub fn disable_interrupt(&mut self) {
+        let systick = unsafe { &*pac::SYSTICK::PTR };
+        systick.ctlr.modify(|_, w| w.stie().clear_bit());
+    }
+}
+
+#[cfg(all(feature = "rtic", not(feature = "time-driver-systick")))]
+impl<const TIMER_HZ: u32> rtic_monotonic::Monotonic for SysTickMonotonic<TIMER_HZ> {
+    type Instant = Instant<TIMER_HZ>;
+    type Duration = Duration<TIMER_HZ>;
+
+    fn now(&mut self) -> Self::Instant {
+        Self::now()
+    }
+
+    fn set_compare(&mut self, instant: Self::Instant) {
+        SysTickMonotonic::set_compare(self, instant)
+    }
+
+    fn clear_compare_flag(&mut self) {
+        clear_flag();
+    }
+
+    fn zero() -> Self::Instant {
+        Instant::from_ticks(0)
+    }
+
+    unsafe fn reset(&mut self) {
+        write_compare(u64::MAX);
+        clear_flag();
+        start_counter(true);
+    }
+
+    fn enable_timer(&mut self) {
+        self.enable_interrupt();
     }
+
+    fn disable_timer(&mut self) {
+        self.disable_interrupt();
+    }
+}
+
+/// (Re)start counter from zero, upcount, HCLK/8
+pub(crate) fn start_counter(interrupt: bool) {
+    let systick = unsafe { &*pac::SYSTICK::PTR };
+
+    systick.ctlr.modify(|_, w| {
+        w.init()
+            .set_bit()
+            .mode()
+            .upcount()
+            .stre()
+            .clear_bit() // no reload
+            .stclk()
+            .hclk_div8()
+            .stie()
+            .bit(interrupt)
+            .ste()
+            .set_bit()
+    });
+}
+
+/// Read the 64-bit counter as two 32-bit halves, retry when the low half wraps
+pub(crate) fn read_counter() -> u64 {
+    let systick = unsafe { &*pac::SYSTICK::PTR };
+    let cnt = &systick.cnt as *const _ as *const u32;
+
+    loop {
+        let hi = unsafe { ptr::read_volatile(cnt.add(1)) };
+        let lo = unsafe { ptr::read_volatile(cnt) };
+        if hi == unsafe { ptr::read_volatile(cnt.add(1)) } {
+            return (hi as u64) << 32 | lo as u64;
+        }
+    }
+}
+
+pub(crate) fn write_compare(val: u64) {
+    let systick = unsafe { &*pac::SYSTICK::PTR };
+    let cmp = &systick.cmp as *const _ as *mut u32;
+
+    // avoid a spurious match between the two writes
+    unsafe {
+        ptr::write_volatile(cmp.add(1), u32::MAX);
+        ptr::write_volatile(cmp, val as u32);
+        ptr::write_volatile(cmp.add(1), (val >> 32) as u32);
+    }
+}
+
+pub(crate) fn clear_flag() {
+    let systick = unsafe { &*pac::SYSTICK::PTR };
+    systick.sr.write(|w| w.cntif().clear_bit());
 }
@@ -1,6 +1,43 @@
 //! TMRx Timer.
 //!
 //! 4 26-bit timers TMR0 to TMR3. TMR1 and TMR2 support DMA.
+//!
+//! In capture mode, the hardware does not record raw timestamps. Instead, each FIFO entry is the
+//! number of Fsys cycles between two consecutive selected edges, i.e. the period (rising/falling)
+//! or the width of one level segment (both edges).
+
+use fugit::HertzU32 as Hertz;
+
+use crate::gpio::Level;
+use crate::traits::pin_trait;
+use crate::{into_ref, pac, peripherals, Peripheral, PeripheralRef};
+
+/// Max counter value, 26-bit
+pub const MAX_COUNT: u32 = 0x3ff_ffff;
+/// Max captured segment length, 25-bit as bit 25 holds the level
+pub const MAX_CAPTURE: u32 = 0x1ff_ffff;
+
+// R8_TMRx_CTRL_MOD
+const RB_TMR_MODE_IN: u8 = 0x01;
+const RB_TMR_ALL_CLEAR: u8 = 0x02;
+const RB_TMR_COUNT_EN: u8 = 0x04;
+const RB_TMR_OUT_EN: u8 = 0x08;
+const RB_TMR_OUT_POLAR: u8 = 0x10;
+const RB_TMR_CAP_EDGE_SHIFT: u8 = 6;
+
+// R8_TMRx_CTRL_DMA
+const RB_TMR_DMA_ENABLE: u8 = 0x01;
+const RB_TMR_DMA_LOOP: u8 = 0x04;
+
+// R8_TMRx_INTER_EN, R8_TMRx_INT_FLAG
+const RB_TMR_IF_CYC_END: u8 = 0x01;
+const RB_TMR_IF_DATA_ACT: u8 = 0x02;
+const RB_TMR_IF_FIFO_HF: u8 = 0x04;
+const RB_TMR_IF_DMA_END: u8 = 0x08;
+const RB_TMR_IF_FIFO_OV: u8 = 0x10;
+
+/// In edge-to-edge capture mode, bit 25 is the level of the measured segment
+const CAP_LEVEL_BIT: u32 = 1 << 25;
 
 #[derive(Clone, Copy)]
 pub enum InputCaptureMode {
@@ -8,3 +45,585 @@ pub enum InputCaptureMode {
     Falling,
     BothEdges,
 }
+
+impl InputCaptureMode {
+    // RB_TMR_CAP_EDGE
+    fn cap_edge(self) -> u8 {
+        match self {
+            InputCaptureMode::BothEdges => 0b01,
+            InputCaptureMode::Falling => 0b10,
+            InputCaptureMode::Rising => 0b11,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Capture FIFO overflowed, some edges are lost
+    Overrun,
+    /// No edge within the capture timeout
+    Timeout,
+}
+
+/// Timer events, for interrupt enable and flag check
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// Counter reached end value. In capture mode, capture timeout.
+    CycleEnd,
+    /// New capture data, or PWM cycle end
+    DataActive,
+    /// FIFO half full
+    FifoHalfFull,
+    /// DMA transfer end, TMR1 and TMR2 only
+    DmaEnd,
+    /// FIFO overflow
+    FifoOverflow,
+}
+
+impl Event {
+    fn mask(self) -> u8 {
+        match self {
+            Event::CycleEnd => RB_TMR_IF_CYC_END,
+            Event::DataActive => RB_TMR_IF_DATA_ACT,
+            Event::FifoHalfFull => RB_TMR_IF_FIFO_HF,
+            Event::DmaEnd => RB_TMR_IF_DMA_END,
+            Event::FifoOverflow => RB_TMR_IF_FIFO_OV,
+        }
+    }
+}
+
+/// A captured segment
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Capture {
+    /// Length of the segment, in Fsys cycles
+    pub ticks: u32,
+    /// Level of the segment, only meaningful for [`InputCaptureMode::BothEdges`]
+    pub level: Level,
+}
+
+impl Capture {
+    /// Decode a raw capture word, e.g. from [`DmaCapture::blocking_read`]
+    pub fn from_raw(raw: u32) -> Self {
+        Self {
+            ticks: raw & MAX_CAPTURE,
+            level: (raw & CAP_LEVEL_BIT != 0).into(),
+        }
+    }
+
+    /// Segment length in microseconds
+    pub fn as_micros(&self) -> u32 {
+        let hclk = crate::sysctl::clocks().hclk.to_Hz() as u64;
+        ((self.ticks as u64) * 1_000_000 / hclk) as u32
+    }
+}
+
+/// Input capture driver
+pub struct InputCapture<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    mode: InputCaptureMode,
+}
+
+impl<'d, T: Instance> InputCapture<'d, T> {
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        pin: impl Peripheral<P = impl TimerPin<T>> + 'd,
+        mode: InputCaptureMode,
+    ) -> Self {
+        into_ref!(peri, pin);
+
+        <T as crate::rcc::sealed::RccPeripheral>::enable();
+
+        pin.set_as_input();
+        T::set_remap(pin.is_remap());
+
+        let rb = T::regs();
+        rb.ctrl_mod.write(|w| unsafe { w.bits(RB_TMR_ALL_CLEAR) });
+        // capture timeout
+        rb.cnt_end.write(|w| unsafe { w.bits(MAX_CAPTURE) });
+        rb.ctrl_mod.write(|w| unsafe {
+            w.bits(RB_TMR_MODE_IN | RB_TMR_COUNT_EN | (mode.cap_edge() << RB_TMR_CAP_EDGE_SHIFT))
+        });
+        rb.int_flag.write(|w| unsafe { w.bits(0xff) });
+
+        Self { _peri: peri, mode }
+    }
+
+    pub fn mode(&self) -> InputCaptureMode {
+        self.mode
+    }
+
+    /// Set capture timeout in Fsys cycles. No edge within the timeout raises [`Event::CycleEnd`].
+    pub fn set_timeout(&mut self, ticks: u32) {
+        T::regs().cnt_end.write(|w| unsafe { w.bits(ticks.min(MAX_CAPTURE)) });
+    }
+
+    /// Discard all pending captures
+    pub fn clear(&mut self) {
+        let rb = T::regs();
+        rb.ctrl_mod.modify(|r, w| unsafe { w.bits(r.bits() | RB_TMR_ALL_CLEAR) });
+        rb.ctrl_mod.modify(|r, w| unsafe { w.bits(r.bits() & !RB_TMR_ALL_CLEAR) });
+        rb.int_flag.write(|w| unsafe { w.bits(0xff) });
+    }
+
+    /// Number of captures waiting in the FIFO
+    pub fn pending(&self) -> u8 {
+        T::regs().fifo_count.read().bits()
+    }
+
+    /// Read one capture, non-blocking
+    pub fn read(&mut self) -> nb::Result<Capture, Error> {
+        let rb = T::regs();
+        let flags = rb.int_flag.read().bits();
+        if flags & RB_TMR_IF_FIFO_OV != 0 {
+            rb.int_flag.write(|w| unsafe { w.bits(RB_TMR_IF_FIFO_OV) });
+            return Err(nb::Error::Other(Error::Overrun));
+        }
+        if rb.fifo_count.read().bits() != 0 {
+            rb.int_flag.write(|w| unsafe { w.bits(RB_TMR_IF_DATA_ACT) });
+            return Ok(Capture::from_raw(rb.fifo.read().bits()));
+        }
+        if flags & RB_TMR_IF_CYC_END != 0 {
+            rb.int_flag.write(|w| unsafe { w.bits(RB_TMR_IF_CYC_END) });
+            return Err(nb::Error::Other(Error::Timeout));
+        }
+        Err(nb::Error::WouldBlock)
+    }
+
+    pub fn blocking_read(&mut self) -> Result<Capture, Error> {
+        nb::block!(self.read())
+    }
+
+    pub fn enable_interrupt(&mut self, event: Event) {
+        T::regs()
+            .inter_en
+            .modify(|r, w| unsafe { w.bits(r.bits() | event.mask()) });
+    }
+
+    pub fn disable_interrupt(&mut self, event: Event) {
+        T::regs()
+            .inter_en
+            .modify(|r, w| unsafe { w.bits(r.bits() & !event.mask()) });
+    }
+
+    pub fn is_pending(&self, event: Event) -> bool {
+        T::regs().int_flag.read().bits() & event.mask() != 0
+    }
+
+    /// Call this in IRQ handler, to clear flag
+    pub fn clear_pending(&mut self, event: Event) {
+        T::regs().int_flag.write(|w| unsafe { w.bits(event.mask()) });
+    }
+}
+
+impl<'d, T: Instance> Drop for InputCapture<'d, T> {
+    fn drop(&mut self) {
+        let rb = T::regs();
+        rb.inter_en.write(|w| unsafe { w.bits(0) });
+        rb.ctrl_mod.write(|w| unsafe { w.bits(RB_TMR_ALL_CLEAR) });
+        <T as crate::rcc::sealed::RccPeripheral>::disable();
+    }
+}
+
+/// Frequency meter, measures rising-edge to rising-edge periods.
+///
+/// Measurable range is about `Fsys / 2^25` to `Fsys / 2`, e.g. 1.8Hz to 30MHz at 60MHz.
+pub struct FrequencyMeter<'d, T: Instance> {
+    capture: InputCapture<'d, T>,
+}
+
+impl<'d, T: Instance> FrequencyMeter<'d, T> {
+    pub fn new(peri: impl Peripheral<P = T> + 'd, pin: impl Peripheral<P = impl TimerPin<T>> + 'd) -> Self {
+        Self {
+            capture: InputCapture::new(peri, pin, InputCaptureMode::Rising),
+        }
+    }
+
+    /// Measure the average period over `periods` cycles, in Fsys cycles.
+    pub fn blocking_period_ticks(&mut self, periods: u8) -> Result<u32, Error> {
+        let periods = periods.max(1);
+        self.capture.clear();
+        // first capture is the partial period since counter start
+        self.capture.blocking_read()?;
+
+        let mut sum: u64 = 0;
+        for _ in 0..periods {
+            sum += self.capture.blocking_read()?.ticks as u64;
+        }
+        Ok((sum / periods as u64) as u32)
+    }
+
+    /// Measure the input frequency, averaged over `periods` cycles.
+    pub fn blocking_frequency(&mut self, periods: u8) -> Result<Hertz, Error> {
+        let ticks = self.blocking_period_ticks(periods)?;
+        let hclk = crate::sysctl::clocks().hclk.to_Hz();
+        Ok(Hertz::from_raw(hclk / ticks.max(1)))
+    }
+
+    /// Same as [`Self::blocking_frequency`], but in milli-hertz, for low frequency signals like tachometers.
+    pub fn blocking_frequency_millihertz(&mut self, periods: u8) -> Result<u32, Error> {
+        let ticks = self.blocking_period_ticks(periods)?;
+        let hclk = crate::sysctl::clocks().hclk.to_Hz() as u64;
+        Ok((hclk * 1000 / (ticks.max(1) as u64)) as u32)
+    }
+
+    pub fn set_timeout(&mut self, ticks: u32) {
+        self.capture.set_timeout(ticks);
+    }
+
+    pub fn release(self) -> InputCapture<'d, T> {
+        self.capture
+    }
+}
+
+/// Result of a pulse measurement, in Fsys cycles
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PulseMeasurement {
+    pub high_ticks: u32,
+    pub low_ticks: u32,
+}
+
+impl PulseMeasurement {
+    pub fn period_ticks(&self) -> u32 {
+        self.high_ticks + self.low_ticks
+    }
+
+    /// Duty cycle of the high level, in per mille
+    pub fn duty_permille(&self) -> u16 {
+        let period = self.period_ticks().max(1) as u64;
+        ((self.high_ticks as u64) * 1000 / period) as u16
+    }
+
+    pub fn frequency(&self) -> Hertz {
+        Hertz::from_raw(crate::sysctl::clocks().hclk.to_Hz() / self.period_ticks().max(1))
+    }
+}
+
+/// Pulse width and duty cycle measurement, captures every edge.
+///
+/// Useful for IR receivers, where each segment is a mark or a space.
+pub struct PulseWidth<'d, T: Instance> {
+    capture: InputCapture<'d, T>,
+}
+
+impl<'d, T: Instance> PulseWidth<'d, T> {
+    pub fn new(peri: impl Peripheral<P = T> + 'd, pin: impl Peripheral<P = impl TimerPin<T>> + 'd) -> Self {
+        Self {
+            capture: InputCapture::new(peri, pin, InputCaptureMode::BothEdges),
+        }
+    }
+
+    /// Read next segment, with its level
+    pub fn read(&mut self) -> nb::Result<Capture, Error> {
+        self.capture.read()
+    }
+
+    /// Read segments into `buf` until it is full or a timeout ends the frame.
+    /// Returns number of segments read.
+    pub fn blocking_read_frame(&mut self, buf: &mut [Capture]) -> Result<usize, Error> {
+        for (i, slot) in buf.iter_mut().enumerate() {
+            match self.capture.blocking_read() {
+                Ok(c) => *slot = c,
+                Err(Error::Timeout) if i > 0 => return Ok(i),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Measure one high pulse and one low pulse.
+    pub fn blocking_measure(&mut self) -> Result<PulseMeasurement, Error> {
+        self.capture.clear();
+        // first capture is partial
+        self.capture.blocking_read()?;
+
+        let a = self.capture.blocking_read()?;
+        let b = self.capture.blocking_read()?;
+        let (high, low) = match a.level {
+            Level::High => (a, b),
+            Level::Low => (b, a),
+        };
+        Ok(PulseMeasurement {
+            high_ticks: high.ticks,
+            low_ticks: low.ticks,
+        })
+    }
+
+    pub fn set_timeout(&mut self, ticks: u32) {
+        self.capture.set_timeout(ticks);
+    }
+
+    pub fn release(self) -> InputCapture<'d, T> {
+        self.capture
+    }
+}
+
+/// PWM output polarity
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Polarity {
+    /// Idle low, duty is high level
+    #[default]
+    ActiveHigh,
+    /// Idle high, duty is low level
+    ActiveLow,
+}
+
+/// PWM output with duty values streamed from RAM by DMA, TMR1 and TMR2 only.
+///
+/// Each DMA word is the active width of one PWM cycle, in Fsys cycles.
+pub struct DmaPwm<'d, T: DmaInstance> {
+    _peri: PeripheralRef<'d, T>,
+}
+
+impl<'d, T: DmaInstance> DmaPwm<'d, T> {
+    /// `period` is the PWM cycle, in Fsys cycles.
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        pin: impl Peripheral<P = impl TimerPin<T>> + 'd,
+        period: u32,
+        polarity: Polarity,
+    ) -> Self {
+        into_ref!(peri, pin);
+
+        <T as crate::rcc::sealed::RccPeripheral>::enable();
+
+        match polarity {
+            Polarity::ActiveHigh => pin.set_low(),
+            Polarity::ActiveLow => pin.set_high(),
+        }
+        pin.set_as_output_with_drive_low();
+        T::set_remap(pin.is_remap());
+
+        let rb = T::regs();
+        rb.ctrl_mod.write(|w| unsafe { w.bits(RB_TMR_ALL_CLEAR) });
+        rb.cnt_end.write(|w| unsafe { w.bits(period.min(MAX_COUNT)) });
+        rb.fifo.write(|w| unsafe { w.bits(0) });
+        let polar = match polarity {
+            Polarity::ActiveHigh => 0,
+            Polarity::ActiveLow => RB_TMR_OUT_POLAR,
+        };
+        // PWM repeat 1 time, so every DMA word is exactly one cycle
+        rb.ctrl_mod.write(|w| unsafe { w.bits(RB_TMR_OUT_EN | polar) });
+        rb.int_flag.write(|w| unsafe { w.bits(0xff) });
+
+        Self { _peri: peri }
+    }
+
+    /// PWM cycle for a given frequency, in Fsys cycles.
+    pub fn period_for(freq: Hertz) -> u32 {
+        crate::sysctl::clocks().hclk.to_Hz() / freq.to_Hz()
+    }
+
+    pub fn set_period(&mut self, period: u32) {
+        T::regs().cnt_end.write(|w| unsafe { w.bits(period.min(MAX_COUNT)) });
+    }
+
+    /// Stream all duty values once, and stop. The table is `&mut`, so it's in RAM, DMA can't read flash.
+    pub fn blocking_write(&mut self, duty: &mut [u32]) {
+        if duty.is_empty() {
+            return;
+        }
+        let rb = T::regs();
+
+        start_dma::<T>(duty.as_mut_ptr() as u32, duty.len(), false);
+        rb.ctrl_mod.modify(|r, w| unsafe { w.bits(r.bits() | RB_TMR_COUNT_EN) });
+
+        while rb.int_flag.read().bits() & RB_TMR_IF_DMA_END == 0 {}
+        // last value is loaded, wait for its cycle to finish
+        rb.int_flag.write(|w| unsafe { w.bits(RB_TMR_IF_DATA_ACT) });
+        while rb.int_flag.read().bits() & RB_TMR_IF_DATA_ACT == 0 {}
+
+        stop_dma::<T>();
+        rb.ctrl_mod.modify(|r, w| unsafe { w.bits(r.bits() & !RB_TMR_COUNT_EN) });
+        rb.fifo.write(|w| unsafe { w.bits(0) });
+    }
+
+    /// Repeat the duty table forever, until [`Self::stop`] is called. The table is in RAM and
+    /// kept by DMA, hence `&'static mut`.
+    pub fn start_loop(&mut self, duty: &'static mut [u32]) {
+        if duty.is_empty() {
+            return;
+        }
+        start_dma::<T>(duty.as_mut_ptr() as u32, duty.len(), true);
+        T::regs()
+            .ctrl_mod
+            .modify(|r, w| unsafe { w.bits(r.bits() | RB_TMR_COUNT_EN) });
+    }
+
+    pub fn stop(&mut self) {
+        let rb = T::regs();
+        stop_dma::<T>();
+        rb.ctrl_mod.modify(|r, w| unsafe { w.bits(r.bits() & !RB_TMR_COUNT_EN) });
+        rb.fifo.write(|w| unsafe { w.bits(0) });
+    }
+}
+
+impl<'d, T: DmaInstance> Drop for DmaPwm<'d, T> {
+    fn drop(&mut self) {
+        stop_dma::<T>();
+        T::regs().ctrl_mod.write(|w| unsafe { w.bits(RB_TMR_ALL_CLEAR) });
+        <T as crate::rcc::sealed::RccPeripheral>::disable();
+    }
+}
+
+/// Encode GRB colors into a WS2812 duty table, 24 words per LED, MSB first.
+///
+/// `t0h` and `t1h` are high level widths in Fsys cycles, with a period of 1.25us,
+/// use 0.4us and 0.8us. Returns number of words written.
+pub fn encode_ws2812(grb: &[u32], t0h: u32, t1h: u32, buf: &mut [u32]) -> usize {
+    let mut n = 0;
+    for (&color, chunk) in grb.iter().zip(buf.chunks_exact_mut(24)) {
+        for (i, word) in chunk.iter_mut().enumerate() {
+            *word = if color & (1 << (23 - i)) != 0 { t1h } else { t0h };
+        }
+        n += 24;
+    }
+    n
+}
+
+/// Input capture into a RAM buffer by DMA, TMR1 and TMR2 only.
+pub struct DmaCapture<'d, T: DmaInstance> {
+    capture: InputCapture<'d, T>,
+}
+
+impl<'d, T: DmaInstance> DmaCapture<'d, T> {
+    pub fn new(
+        peri: impl Peripheral<P = T> + 'd,
+        pin: impl Peripheral<P = impl TimerPin<T>> + 'd,
+        mode: InputCaptureMode,
+    ) -> Self {
+        Self {
+            capture: InputCapture::new(peri, pin, mode),
+        }
+    }
+
+    /// Set capture timeout in Fsys cycles, which ends a frame.
+    pub fn set_timeout(&mut self, ticks: u32) {
+        self.capture.set_timeout(ticks);
+    }
+
+    /// Capture raw values until `buf` is full, or no edge within the timeout after the first capture.
+    ///
+    /// Returns number of words captured, use [`Capture::from_raw`] to decode them.
+    pub fn blocking_read(&mut self, buf: &mut [u32]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let rb = T::regs();
+        let start = buf.as_mut_ptr() as u32;
+
+        self.capture.clear();
+        start_dma::<T>(start, buf.len(), false);
+
+        let n = loop {
+            let flags = rb.int_flag.read().bits();
+            let n = ((dma_now::<T>().wrapping_sub(start as u16)) / 4) as usize;
+            if flags & RB_TMR_IF_DMA_END != 0 {
+                break buf.len();
+            }
+            if flags & RB_TMR_IF_CYC_END != 0 && n > 0 {
+                break n;
+            }
+            if flags & RB_TMR_IF_FIFO_OV != 0 {
+                stop_dma::<T>();
+                return Err(Error::Overrun);
+            }
+        };
+        stop_dma::<T>();
+        rb.int_flag.write(|w| unsafe { w.bits(0xff) });
+
+        Ok(n)
+    }
+
+    pub fn release(self) -> InputCapture<'d, T> {
+        stop_dma::<T>();
+        self.capture
+    }
+}
+
+/// `addr` is a `u32` buffer in RAM, taken from a `&mut` slice
+fn start_dma<T: DmaInstance>(addr: u32, words: usize, circular: bool) {
+    // DMA address registers are 16-bit offsets into RAM
+    let end = addr + (words as u32) * 4;
+
+    let rb = T::dma_regs();
+    rb.dma_beg.write(|w| unsafe { w.bits(addr as u16) });
+    rb.dma_end.write(|w| unsafe { w.bits(end as u16) });
+    T::regs().int_flag.write(|w| unsafe { w.bits(RB_TMR_IF_DMA_END) });
+    if circular {
+        rb.ctrl_dma.write(|w| unsafe { w.bits(RB_TMR_DMA_LOOP | RB_TMR_DMA_ENABLE) });
+    } else {
+        rb.ctrl_dma.write(|w| unsafe { w.bits(RB_TMR_DMA_ENABLE) });
+    }
+}
+
+fn stop_dma<T: DmaInstance>() {
+    T::dma_regs().ctrl_dma.write(|w| unsafe { w.bits(0) });
+}
+
+fn dma_now<T: DmaInstance>() -> u16 {
+    T::dma_regs().dma_now.read().bits()
+}
+
+// - instance trait
+
+pub(crate) mod sealed {
+    use crate::interrupt;
+
+    pub trait Instance {
+        type Interrupt: interrupt::Interrupt;
+
+        /// TMR1 and TMR2 share the same layout as TMR0 for the first registers.
+        fn regs() -> &'static crate::pac::tmr0::RegisterBlock;
+
+        /// Remap bit in R16_PIN_ALTERNATE
+        fn set_remap(enable: bool);
+    }
+
+    pub trait DmaInstance: Instance {
+        fn dma_regs() -> &'static crate::pac::tmr1::RegisterBlock;
+    }
+}
+
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + crate::rcc::RccPeripheral {}
+pub trait DmaInstance: Instance + sealed::DmaInstance {}
+
+pin_trait!(TimerPin, Instance);
+
+macro_rules! impl_timer {
+    ($inst:ident, $remap_field:ident) => {
+        impl sealed::Instance for peripherals::$inst {
+            type Interrupt = crate::interrupt::$inst;
+
+            fn regs() -> &'static crate::pac::tmr0::RegisterBlock {
+                unsafe { &*(pac::$inst::PTR as *const crate::pac::tmr0::RegisterBlock) }
+            }
+
+            fn set_remap(enable: bool) {
+                let gpioctl = unsafe { &*pac::GPIOCTL::PTR };
+                gpioctl.pin_alternate.modify(|_, w| w.$remap_field().bit(enable));
+            }
+        }
+        impl Instance for peripherals::$inst {}
+    };
+}
+
+impl_timer!(TMR0, tmr0);
+impl_timer!(TMR1, tmr1);
+impl_timer!(TMR2, tmr2);
+impl_timer!(TMR3, tmr3);
+
+macro_rules! impl_dma_timer {
+    ($inst:ident) => {
+        impl sealed::DmaInstance for peripherals::$inst {
+            fn dma_regs() -> &'static crate::pac::tmr1::RegisterBlock {
+                unsafe { &*(pac::$inst::PTR as *const crate::pac::tmr1::RegisterBlock) }
+            }
+        }
+        impl DmaInstance for peripherals::$inst {}
+    };
+}
+
+impl_dma_timer!(TMR1);
+impl_dma_timer!(TMR2);
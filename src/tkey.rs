@@ -0,0 +1,214 @@
+//! TouchKey, capacitive touch sensing
+//!
+//! The TouchKey unit charges the pin for a number of cycles, discharges it, then samples the
+//! remaining voltage with the ADC. A finger adds capacitance and lowers the sample.
+//! Any ADC pin can be used as a touch channel.
+
+use crate::adc::sealed::{AdcPin as _, Instance as _};
+use crate::adc::{AdcPin, Gain, SamplingClock};
+use crate::gpio::AnyPin;
+use crate::peripherals::{ADC, TKEY};
+use crate::{into_ref, Peripheral, PeripheralRef};
+
+// R8_TKEY_COUNT
+const RB_TKEY_CHARG_CNT: u8 = 0x1f;
+const RB_TKEY_DISCH_CNT: u8 = 0xe0;
+// R8_TKEY_CONVERT
+const RB_TKEY_START: u8 = 0x01;
+// R8_TKEY_CFG
+const RB_TKEY_PWR_ON: u8 = 0x01;
+
+/// Max number of touch channels, one per ADC pin
+pub const MAX_CHANNELS: usize = 14;
+
+/// Fractional bits of the baseline, so slow drift is followed without rounding bias
+const BASELINE_FRAC_BITS: u32 = 8;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Config {
+    /// Charge time, in ADC clock cycles, 0 to 31
+    pub charge_cycles: u8,
+    /// Discharge time, 0 to 7
+    pub discharge_cycles: u8,
+    /// Drop below the baseline that counts as a press
+    pub threshold: u16,
+    /// A pressed key is released when the drop is below `threshold - hysteresis`
+    pub hysteresis: u16,
+    /// Baseline filter, the baseline moves by `1 / 2^baseline_shift` of the difference per sample,
+    /// up to 16
+    pub baseline_shift: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            charge_cycles: 0x10,
+            discharge_cycles: 0,
+            threshold: 100,
+            hysteresis: 20,
+            baseline_shift: 4,
+        }
+    }
+}
+
+/// Touch events of one scan, bit `n` is channel `n` of the `TouchKey`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Events {
+    pub pressed: u16,
+    pub released: u16,
+}
+
+impl Events {
+    pub fn is_empty(&self) -> bool {
+        self.pressed == 0 && self.released == 0
+    }
+}
+
+/// A touch input pin
+pub struct TouchChannel<'d> {
+    _pin: PeripheralRef<'d, AnyPin>,
+    channel: u8,
+}
+
+impl<'d> TouchChannel<'d> {
+    pub fn new<P: AdcPin<ADC> + crate::gpio::Pin>(pin: impl Peripheral<P = P> + 'd) -> Self {
+        into_ref!(pin);
+
+        <P as crate::adc::sealed::AdcPin<ADC>>::set_as_analog(&mut *pin);
+        let channel = pin.channel();
+
+        Self {
+            _pin: pin.map_into(),
+            channel,
+        }
+    }
+}
+
+pub struct TouchKey<'d, const N: usize> {
+    _tkey: PeripheralRef<'d, TKEY>,
+    _adc: PeripheralRef<'d, ADC>,
+    channels: [TouchChannel<'d>; N],
+    config: Config,
+    /// Fixed point, `BASELINE_FRAC_BITS` fractional bits
+    baseline: [i32; N],
+    pressed: u16,
+}
+
+impl<'d, const N: usize> TouchKey<'d, N> {
+    /// Takes the ADC, touch sensing uses its converter. Captures the baseline of all channels,
+    /// keep the keys untouched.
+    pub fn new(
+        tkey: impl Peripheral<P = TKEY> + 'd,
+        adc: impl Peripheral<P = ADC> + 'd,
+        channels: [TouchChannel<'d>; N],
+        config: Config,
+    ) -> Self {
+        into_ref!(tkey, adc);
+        assert!(N <= MAX_CHANNELS);
+
+        <ADC as crate::rcc::sealed::RccPeripheral>::enable();
+
+        ADC::regs().cfg.write(|w| {
+            w.power_on()
+                .set_bit()
+                .buf_en()
+                .set_bit()
+                .pga_gain()
+                .variant(Gain::GAIN1 as u8)
+                .clk_div()
+                .variant(SamplingClock::_8MHz as u8)
+        });
+        ADC::regs()
+            .tkey_cfg
+            .modify(|r, w| unsafe { w.bits(r.bits() | RB_TKEY_PWR_ON) });
+
+        let mut this = Self {
+            _tkey: tkey,
+            _adc: adc,
+            channels,
+            config,
+            baseline: [0; N],
+            pressed: 0,
+        };
+        this.set_config(config);
+        this.calibrate();
+        this
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        let disch = (config.discharge_cycles << 5) & RB_TKEY_DISCH_CNT;
+        let count = disch | (config.charge_cycles & RB_TKEY_CHARG_CNT);
+        ADC::regs().tkey_count.write(|w| unsafe { w.bits(count) });
+    }
+
+    /// Re-capture the baseline of all channels, averaged over 16 samples
+    pub fn calibrate(&mut self) {
+        for i in 0..N {
+            let mut sum = 0;
+            for _ in 0..16 {
+                sum += self.read_raw(i) as u32;
+            }
+            self.baseline[i] = ((sum << BASELINE_FRAC_BITS) / 16) as i32;
+        }
+        self.pressed = 0;
+    }
+
+    /// Single touch sample of the `index`th channel
+    pub fn read_raw(&mut self, index: usize) -> u16 {
+        let rb = ADC::regs();
+
+        rb.channel
+            .modify(|_, w| w.ch_idx().variant(self.channels[index].channel));
+        rb.tkey_convert.write(|w| unsafe { w.bits(RB_TKEY_START) });
+        while rb.tkey_convert.read().bits() & RB_TKEY_START != 0 {}
+
+        rb.data.read().data().bits()
+    }
+
+    pub fn baseline(&self, index: usize) -> u16 {
+        let half = 1 << (BASELINE_FRAC_BITS - 1);
+        ((self.baseline[index] + half) >> BASELINE_FRAC_BITS) as u16
+    }
+
+    pub fn is_pressed(&self, index: usize) -> bool {
+        self.pressed & (1 << index) != 0
+    }
+
+    /// Sample all channels, track the baseline of released keys and report press and release events
+    pub fn scan(&mut self) -> Events {
+        let mut events = Events::default();
+
+        for i in 0..N {
+            let sample = self.read_raw(i);
+            let delta = self.baseline(i) as i32 - sample as i32;
+            let mask = 1 << i;
+
+            if self.pressed & mask == 0 {
+                if delta > self.config.threshold as i32 {
+                    self.pressed |= mask;
+                    events.pressed |= mask;
+                } else {
+                    // follow slow drift, only while released. Division rounds toward zero, the same
+                    // both ways, and the fractional bits keep small differences
+                    let diff = ((sample as i32) << BASELINE_FRAC_BITS) - self.baseline[i];
+                    self.baseline[i] += diff / (1 << self.config.baseline_shift.min(16));
+                }
+            } else if delta < self.config.threshold.saturating_sub(self.config.hysteresis) as i32 {
+                self.pressed &= !mask;
+                events.released |= mask;
+            }
+        }
+
+        events
+    }
+}
+
+impl<'d, const N: usize> Drop for TouchKey<'d, N> {
+    fn drop(&mut self) {
+        let rb = ADC::regs();
+        rb.tkey_cfg.modify(|r, w| unsafe { w.bits(r.bits() & !RB_TKEY_PWR_ON) });
+        rb.cfg.modify(|_, w| w.power_on().clear_bit());
+    }
+}
@@ -110,6 +110,8 @@ impl<'d, T: BasicInstance> UartTx<'d, T> {
     ) -> Result<Self, ConfigError> {
         into_ref!(_peri, tx);
 
+        <T as crate::rcc::sealed::RccPeripheral>::enable();
+
         // set up pin
         tx.set_as_output_with_drive_low();
         T::set_remap(tx.is_remap());
@@ -195,6 +197,14 @@ impl<'d, T: BasicInstance> UartTx<'d, T> {
 
 // embedded-hal
 
+impl<'d, T: BasicInstance> Drop for UartTx<'d, T> {
+    fn drop(&mut self) {
+        self.blocking_flush().ok();
+        T::regs().ier.modify(|_, w| w.txd_en().clear_bit());
+        <T as crate::rcc::sealed::RccPeripheral>::disable();
+    }
+}
+
 impl<'d, T: BasicInstance> core::fmt::Write for UartTx<'d, T> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         self.blocking_write(s.as_bytes()).unwrap();
@@ -269,7 +279,7 @@ pub(crate) mod sealed {
 
     pub trait FullInstance: BasicInstance {}
 }
-pub trait BasicInstance: Peripheral<P = Self> + sealed::BasicInstance + 'static + Send {}
+pub trait BasicInstance: Peripheral<P = Self> + sealed::BasicInstance + crate::rcc::RccPeripheral + Send {}
 
 // UART with CTS, DSR, RI, DCD, DTR, RTS
 pub trait FullInstance: sealed::FullInstance {}
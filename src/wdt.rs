@@ -0,0 +1,133 @@
+//! Watchdog timer
+//!
+//! An 8-bit up counter, incremented every 131072 HCLK cycles. Overflow from 0xFF resets the chip,
+//! or raises the `WDOG_BAT` interrupt. Feeding writes the start value back to the counter.
+
+use fugit::MicrosDurationU32;
+
+use crate::interrupt::Interrupt;
+use crate::peripherals::WDOG;
+use crate::{into_ref, pac, sysctl, with_safe_access, Peripheral, PeripheralRef};
+
+// R8_RST_WDOG_CTRL
+const WDOG_RST_EN: u8 = 0x02;
+const WDOG_INT_EN: u8 = 0x04;
+const WDOG_INT_FLAG: u8 = 0x10;
+
+/// HCLK cycles per counter tick
+const CYCLES_PER_TICK: u64 = 131072;
+
+/// Action on counter overflow
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Reset the chip
+    Reset,
+    /// Raise the `WDOG_BAT` interrupt, call [`Watchdog::on_interrupt`] from its handler
+    Interrupt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Timeout longer than 256 ticks, or shorter than 1 tick
+    InvalidTimeout,
+}
+
+pub struct Watchdog<'d> {
+    _inner: PeripheralRef<'d, WDOG>,
+    reload: u8,
+}
+
+impl<'d> Watchdog<'d> {
+    pub fn new(p: impl Peripheral<P = WDOG> + 'd) -> Self {
+        into_ref!(p);
+
+        Self { _inner: p, reload: 0 }
+    }
+
+    /// Longest timeout at the current HCLK
+    pub fn max_timeout() -> MicrosDurationU32 {
+        ticks_to_duration(256)
+    }
+
+    /// Start the watchdog, overflow after `timeout`, rounded down to a counter tick.
+    ///
+    /// The counter itself always runs, [`Watchdog::stop`] only disables reset and interrupt.
+    pub fn start(&mut self, timeout: MicrosDurationU32, mode: Mode) -> Result<(), Error> {
+        let hclk = sysctl::clocks().hclk.to_Hz() as u64;
+        let ticks = timeout.to_micros() as u64 * hclk / CYCLES_PER_TICK / 1_000_000;
+        if ticks == 0 || ticks > 256 {
+            return Err(Error::InvalidTimeout);
+        }
+        self.reload = (256 - ticks) as u8;
+        self.feed();
+
+        let en = match mode {
+            Mode::Reset => WDOG_RST_EN,
+            Mode::Interrupt => WDOG_INT_EN,
+        };
+        with_safe_access(|| unsafe {
+            sys().rst_wdog_ctrl.modify(|r, w| {
+                let val = r.bits() & !(WDOG_RST_EN | WDOG_INT_EN);
+                w.bits(val | en | WDOG_INT_FLAG)
+            });
+        });
+        if mode == Mode::Interrupt {
+            unsafe { crate::interrupt::WDOG_BAT::enable() };
+        }
+
+        Ok(())
+    }
+
+    /// Disable reset and interrupt, the counter keeps running
+    pub fn stop(&mut self) {
+        with_safe_access(|| unsafe {
+            sys()
+                .rst_wdog_ctrl
+                .modify(|r, w| w.bits(r.bits() & !(WDOG_RST_EN | WDOG_INT_EN)));
+        });
+    }
+
+    /// Restart the countdown
+    pub fn feed(&mut self) {
+        // the counter is not safe-access protected
+        sys().wdog_count.write(|w| unsafe { w.bits(self.reload) });
+    }
+
+    /// Time until overflow
+    pub fn remaining(&self) -> MicrosDurationU32 {
+        let count = sys().wdog_count.read().bits();
+        ticks_to_duration(256 - count as u32)
+    }
+
+    pub fn is_pending() -> bool {
+        sys().rst_wdog_ctrl.read().bits() & WDOG_INT_FLAG != 0
+    }
+
+    /// Call this in IRQ handler, to clear flag. Returns `true` if the watchdog overflowed.
+    ///
+    /// `WDOG_BAT` is shared with the battery monitor.
+    pub fn on_interrupt() -> bool {
+        if !Self::is_pending() {
+            return false;
+        }
+        with_safe_access(|| unsafe {
+            sys().rst_wdog_ctrl.modify(|r, w| w.bits(r.bits() | WDOG_INT_FLAG));
+        });
+        true
+    }
+
+    /// The last reset was caused by the watchdog
+    pub fn caused_reset() -> bool {
+        crate::ResetReason::read().is_watchdog()
+    }
+}
+
+fn sys() -> &'static pac::sys::RegisterBlock {
+    unsafe { &*pac::SYS::PTR }
+}
+
+fn ticks_to_duration(ticks: u32) -> MicrosDurationU32 {
+    let hclk = sysctl::clocks().hclk.to_Hz() as u64;
+    MicrosDurationU32::from_ticks((ticks as u64 * CYCLES_PER_TICK * 1_000_000 / hclk) as u32)
+}